@@ -8,25 +8,61 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-/// A very simple BitVector type.
-pub struct BitVector {
+//! Bit sets. `BitSet` is a set of small integers stored one bit per
+//! possible element, and `BitMatrix` is a matrix of bits, i.e. one
+//! `BitSet` per row. Both are indexed by any `Idx` type, `usize` by
+//! default; `BitVector` is the `usize` flavor of `BitSet`.
+//!
+//! Both types are sized up front: the bits are stored in whole `u64`
+//! words, so a vector created for `n` bits can hold any bit below `n`
+//! rounded up to a multiple of 64. Binary operations such as `union`
+//! require both operands to have the same number of words.
+//!
+//! For large domains where most sets stay small, `HybridBitSet` keeps
+//! a few elements in a sorted array and only switches to a dense
+//! `BitVector` when it grows, and `SparseBitMatrix` only allocates the
+//! rows that are actually used.
+//!
+//! `SnapshotBitVector` and `SnapshotBitMatrix` are variants whose
+//! changes are recorded in an undo log, word by word, so they can be
+//! rolled back like a `SnapshotVec`. Their `*Storage` forms have no log
+//! of their own and share an external one through `with_log`.
+
+use std::iter::Enumerate;
+use std::marker::PhantomData;
+use std::slice;
+
+use idx::Idx;
+use snapshot_vec as sv;
+use undo_log::{Rollback, Snapshots, UndoLogs, VecLog};
+
+/// A very simple bit set type, indexed by `I`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BitSet<I: Idx = usize> {
     data: Vec<u64>,
+    marker: PhantomData<fn(I)>,
 }
 
-impl BitVector {
-    pub fn new(num_bits: usize) -> BitVector {
+/// A `BitSet` indexed by plain `usize`s.
+pub type BitVector = BitSet<usize>;
+
+impl<I: Idx> BitSet<I> {
+    pub fn new(num_bits: usize) -> BitSet<I> {
         let num_words = u64s(num_bits);
-        BitVector { data: vec![0; num_words] }
+        BitSet {
+            data: vec![0; num_words],
+            marker: PhantomData,
+        }
     }
 
-    pub fn contains(&self, bit: usize) -> bool {
-        let (word, mask) = word_mask(bit);
+    pub fn contains(&self, bit: I) -> bool {
+        let (word, mask) = word_mask(bit.to_usize());
         (self.data[word] & mask) != 0
     }
 
     /// Returns true if the bit has changed.
-    pub fn insert(&mut self, bit: usize) -> bool {
-        let (word, mask) = word_mask(bit);
+    pub fn insert(&mut self, bit: I) -> bool {
+        let (word, mask) = word_mask(bit.to_usize());
         let data = &mut self.data[word];
         let value = *data;
         let new_value = value | mask;
@@ -34,110 +70,244 @@ impl BitVector {
         new_value != value
     }
 
-    pub fn insert_all(&mut self, all: &BitVector) -> bool {
-        assert!(self.data.len() == all.data.len());
+    /// Returns true if the bit has changed.
+    pub fn remove(&mut self, bit: I) -> bool {
+        let (word, mask) = word_mask(bit.to_usize());
+        let data = &mut self.data[word];
+        let value = *data;
+        let new_value = value & !mask;
+        *data = new_value;
+        new_value != value
+    }
+
+    /// Removes every bit, keeping the capacity.
+    pub fn clear(&mut self) {
+        for word in &mut self.data {
+            *word = 0;
+        }
+    }
+
+    /// Same as `union`.
+    pub fn insert_all(&mut self, all: &BitSet<I>) -> bool {
+        self.union(all)
+    }
+
+    /// Adds every bit of `other` to `self`. Returns true if anything
+    /// changed.
+    pub fn union(&mut self, other: &BitSet<I>) -> bool {
+        self.bitwise(other, |a, b| a | b)
+    }
+
+    /// Removes from `self` every bit that is not in `other`. Returns
+    /// true if anything changed.
+    pub fn intersect(&mut self, other: &BitSet<I>) -> bool {
+        self.bitwise(other, |a, b| a & b)
+    }
+
+    /// Removes from `self` every bit that is in `other`. Returns true
+    /// if anything changed.
+    pub fn subtract(&mut self, other: &BitSet<I>) -> bool {
+        self.bitwise(other, |a, b| a & !b)
+    }
+
+    fn bitwise(&mut self, other: &BitSet<I>, op: impl Fn(u64, u64) -> u64) -> bool {
+        assert_eq!(self.data.len(), other.data.len());
         let mut changed = false;
-        for (i, j) in self.data.iter_mut().zip(&all.data) {
+        for (i, &j) in self.data.iter_mut().zip(&other.data) {
             let value = *i;
-            *i = value | *j;
-            if value != *i {
-                changed = true;
-            }
+            *i = op(value, j);
+            changed |= value != *i;
         }
         changed
     }
 
+    /// Returns true if every bit of `self` is also in `other`.
+    pub fn is_subset(&self, other: &BitSet<I>) -> bool {
+        assert_eq!(self.data.len(), other.data.len());
+        self.data
+            .iter()
+            .zip(&other.data)
+            .all(|(&a, &b)| a & !b == 0)
+    }
+
+    /// Returns the number of bits that are set.
+    pub fn count(&self) -> usize {
+        self.data
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns true if no bit is set.
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|&word| word == 0)
+    }
+
+    /// Returns the number of bits this vector can hold, which is the
+    /// size it was created (or grown) with, rounded up to a multiple
+    /// of 64.
+    pub fn capacity(&self) -> usize {
+        self.data.len() * 64
+    }
+
+    /// Makes room for at least `num_bits` bits. New bits are unset.
     pub fn grow(&mut self, num_bits: usize) {
         let num_words = u64s(num_bits);
-        let extra_words = self.data.len() - num_words;
-        self.data.extend((0..extra_words).map(|_| 0));
+        if num_words > self.data.len() {
+            self.data.resize(num_words, 0);
+        }
+    }
+
+    /// Returns the underlying words. Bit `i` is bit `i % 64` of word
+    /// `i / 64`.
+    pub fn words(&self) -> &[u64] {
+        &self.data
     }
 
     /// Iterates over indexes of set bits in a sorted order
-    pub fn iter<'a>(&'a self) -> BitVectorIter<'a> {
-        BitVectorIter {
-            iter: self.data.iter(),
-            current: 0,
-            idx: 0,
-        }
+    pub fn iter(&self) -> BitIter<'_, I> {
+        BitIter::new(&self.data)
     }
 }
 
-pub struct BitVectorIter<'a> {
-    iter: ::std::slice::Iter<'a, u64>,
+/// Iterator over the set bits of a `BitSet` or of a `BitMatrix` row.
+pub struct BitIter<'a, I: Idx = usize> {
+    iter: Enumerate<slice::Iter<'a, u64>>,
     current: u64,
-    idx: usize,
+    base: usize,
+    marker: PhantomData<fn() -> I>,
 }
 
-impl<'a> Iterator for BitVectorIter<'a> {
-    type Item = usize;
-    fn next(&mut self) -> Option<usize> {
+/// A `BitIter` over plain `usize`s.
+pub type BitVectorIter<'a> = BitIter<'a, usize>;
+
+impl<'a, I: Idx> BitIter<'a, I> {
+    fn new(words: &'a [u64]) -> BitIter<'a, I> {
+        BitIter {
+            iter: words.iter().enumerate(),
+            current: 0,
+            base: 0,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, I: Idx> Iterator for BitIter<'a, I> {
+    type Item = I;
+    fn next(&mut self) -> Option<I> {
         while self.current == 0 {
-            self.current = if let Some(&i) = self.iter.next() {
-                if i == 0 {
-                    self.idx += 64;
-                    continue;
-                } else {
-                    self.idx = u64s(self.idx) * 64;
-                    i
-                }
-            } else {
-                return None;
-            }
+            let (index, &word) = self.iter.next()?;
+            self.current = word;
+            self.base = index * 64;
         }
         let offset = self.current.trailing_zeros() as usize;
-        self.current >>= offset;
-        self.current >>= 1; // shift otherwise overflows for 0b1000_0000_…_0000
-        self.idx += offset + 1;
-        return Some(self.idx - 1);
+        // Clear the lowest set bit.
+        self.current &= self.current - 1;
+        Some(I::from_usize(self.base + offset))
     }
 }
 
-/// A "bit matrix" is basically a square matrix of booleans
-/// represented as one gigantic bitvector. In other words, it is as if
-/// you have N bitvectors, each of length N. Note that `elements` here is `N`/
-#[derive(Clone)]
-pub struct BitMatrix {
-    elements: usize,
+/// A "bit matrix" is basically a matrix of booleans represented as one
+/// gigantic bitvector. In other words, it is as if you have one
+/// bitvector per row, each with one bit per column. Rows are indexed by
+/// `R` and columns by `C`.
+///
+/// `BitMatrix::new(N)` creates a square `N x N` matrix of `usize`s,
+/// which is what `elements` refers to; `with_size` creates a matrix of
+/// any shape and index types.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BitMatrix<R: Idx = usize, C: Idx = usize> {
+    num_rows: usize,
+    num_columns: usize,
     vector: Vec<u64>,
+    marker: PhantomData<fn(R, C)>,
 }
 
 impl BitMatrix {
     // Create a new `elements x elements` matrix, initially empty.
     pub fn new(elements: usize) -> BitMatrix {
-        // For every element, we need one bit for every other
-        // element. Round up to an even number of u64s.
-        let u64s_per_elem = u64s(elements);
+        BitMatrix::with_size(elements, elements)
+    }
+
+    /// Returns `N`, the number of rows (and columns).
+    pub fn elements(&self) -> usize {
+        self.num_rows
+    }
+
+    /// Resizes the matrix to `elements x elements`. Bits in rows and
+    /// columns that remain in bounds are kept; new bits are unset.
+    pub fn resize(&mut self, elements: usize) {
+        self.resize_to(elements, elements);
+    }
+}
+
+impl<R: Idx, C: Idx> BitMatrix<R, C> {
+    /// Create a new `num_rows x num_columns` matrix, initially empty.
+    pub fn with_size(num_rows: usize, num_columns: usize) -> BitMatrix<R, C> {
+        // For every row, we need one bit for every column. Round up to
+        // an even number of u64s.
+        let u64s_per_row = u64s(num_columns);
         BitMatrix {
-            elements: elements,
-            vector: vec![0; elements * u64s_per_elem],
+            num_rows,
+            num_columns,
+            vector: vec![0; num_rows * u64s_per_row],
+            marker: PhantomData,
         }
     }
 
-    /// The range of bits for a given element.
-    fn range(&self, element: usize) -> (usize, usize) {
-        let u64s_per_elem = u64s(self.elements);
-        let start = element * u64s_per_elem;
-        (start, start + u64s_per_elem)
+    /// Returns the number of rows.
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
     }
 
-    pub fn add(&mut self, source: usize, target: usize) -> bool {
+    /// Returns the number of columns.
+    pub fn num_columns(&self) -> usize {
+        self.num_columns
+    }
+
+    /// The range of bits for a given row.
+    fn range(&self, row: R) -> (usize, usize) {
+        let row = row.to_usize();
+        assert!(row < self.num_rows, "row {} out of bounds", row);
+        let u64s_per_row = u64s(self.num_columns);
+        let start = row * u64s_per_row;
+        (start, start + u64s_per_row)
+    }
+
+    fn word_mask(&self, column: C) -> (usize, u64) {
+        let column = column.to_usize();
+        assert!(column < self.num_columns, "column {} out of bounds", column);
+        word_mask(column)
+    }
+
+    pub fn add(&mut self, source: R, target: C) -> bool {
         let (start, _) = self.range(source);
-        let (word, mask) = word_mask(target);
-        let mut vector = &mut self.vector[..];
+        let (word, mask) = self.word_mask(target);
+        let vector = &mut self.vector[..];
         let v1 = vector[start + word];
         let v2 = v1 | mask;
         vector[start + word] = v2;
         v1 != v2
     }
 
+    /// Removes the bit `(source, target)`. Returns true if it was set.
+    pub fn remove(&mut self, source: R, target: C) -> bool {
+        let (start, _) = self.range(source);
+        let (word, mask) = self.word_mask(target);
+        let vector = &mut self.vector[..];
+        let v1 = vector[start + word];
+        let v2 = v1 & !mask;
+        vector[start + word] = v2;
+        v1 != v2
+    }
+
     /// Do the bits from `source` contain `target`?
     ///
     /// Put another way, if the matrix represents (transitive)
     /// reachability, can `source` reach `target`?
-    pub fn contains(&self, source: usize, target: usize) -> bool {
+    pub fn contains(&self, source: R, target: C) -> bool {
         let (start, _) = self.range(source);
-        let (word, mask) = word_mask(target);
+        let (word, mask) = self.word_mask(target);
         (self.vector[start + word] & mask) != 0
     }
 
@@ -145,10 +315,10 @@ impl BitMatrix {
     /// `b`. This is an O(n) operation where `n` is the number of
     /// elements (somewhat independent from the actual size of the
     /// intersection, in particular).
-    pub fn intersection(&self, a: usize, b: usize) -> Vec<usize> {
+    pub fn intersection(&self, a: R, b: R) -> Vec<C> {
         let (a_start, a_end) = self.range(a);
         let (b_start, b_end) = self.range(b);
-        let mut result = Vec::with_capacity(self.elements);
+        let mut result = Vec::with_capacity(self.num_columns);
         for (base, (i, j)) in (a_start..a_end).zip(b_start..b_end).enumerate() {
             let mut v = self.vector[i] & self.vector[j];
             for bit in 0..64 {
@@ -156,7 +326,7 @@ impl BitMatrix {
                     break;
                 }
                 if v & 0x1 != 0 {
-                    result.push(base * 64 + bit);
+                    result.push(C::from_usize(base * 64 + bit));
                 }
                 v >>= 1;
             }
@@ -171,7 +341,7 @@ impl BitMatrix {
     /// you have an edge `write -> read`, because in that case
     /// `write` can reach everything that `read` can (and
     /// potentially more).
-    pub fn merge(&mut self, read: usize, write: usize) -> bool {
+    pub fn merge(&mut self, read: R, write: R) -> bool {
         let (read_start, read_end) = self.range(read);
         let (write_start, write_end) = self.range(write);
         let vector = &mut self.vector[..];
@@ -180,14 +350,750 @@ impl BitMatrix {
             let v1 = vector[write_index];
             let v2 = v1 | vector[read_index];
             vector[write_index] = v2;
-            changed = changed | (v1 != v2);
+            changed |= v1 != v2;
+        }
+        changed
+    }
+
+    /// Returns the words of row `source`, in the same layout as
+    /// `BitSet::words`.
+    pub fn row(&self, source: R) -> &[u64] {
+        let (start, end) = self.range(source);
+        &self.vector[start..end]
+    }
+
+    /// Iterates over the columns set in row `source`, in sorted order.
+    pub fn iter(&self, source: R) -> BitIter<'_, C> {
+        BitIter::new(self.row(source))
+    }
+
+    /// Returns the number of bits set in row `source`.
+    pub fn count(&self, source: R) -> usize {
+        self.row(source)
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Removes every bit, keeping the size.
+    pub fn clear(&mut self) {
+        for word in &mut self.vector {
+            *word = 0;
+        }
+    }
+
+    /// Resizes the matrix to `num_rows x num_columns`. Bits in rows and
+    /// columns that remain in bounds are kept; new bits are unset.
+    pub fn resize_to(&mut self, num_rows: usize, num_columns: usize) {
+        let old_words = u64s(self.num_columns);
+        let new_words = u64s(num_columns);
+        let mut vector = vec![0; num_rows * new_words];
+        for row in 0..self.num_rows.min(num_rows) {
+            let old_row = &self.vector[row * old_words..(row + 1) * old_words];
+            let new_row = &mut vector[row * new_words..(row + 1) * new_words];
+            for (new, &old) in new_row.iter_mut().zip(old_row) {
+                *new = old;
+            }
+            if num_columns < self.num_columns && new_words > 0 {
+                // Drop columns that are now out of bounds.
+                new_row[new_words - 1] &= !0 >> (new_words * 64 - num_columns);
+            }
+        }
+        self.num_rows = num_rows;
+        self.num_columns = num_columns;
+        self.vector = vector;
+    }
+}
+
+/// The largest number of elements a `HybridBitSet` keeps in its sparse
+/// form; inserting one more switches it to a dense `BitVector`.
+const SPARSE_MAX: usize = 8;
+
+/// A set of bits below `domain_size` that starts out as a small sorted
+/// array, and switches to a dense `BitVector` once it holds more than a
+/// handful of elements. This keeps sets that stay small cheap even when
+/// the domain is huge, while large sets still get word-at-a-time
+/// operations.
+///
+/// A set never switches back to the sparse form, even if elements are
+/// removed.
+#[derive(Clone, Debug)]
+pub struct HybridBitSet {
+    domain_size: usize,
+    repr: HybridRepr,
+}
+
+#[derive(Clone, Debug)]
+enum HybridRepr {
+    // Sorted, without duplicates, and at most `SPARSE_MAX` long.
+    Sparse(Vec<usize>),
+    Dense(BitVector),
+}
+
+impl HybridBitSet {
+    /// Creates an empty set for the bits `0..domain_size`.
+    pub fn new(domain_size: usize) -> HybridBitSet {
+        HybridBitSet {
+            domain_size,
+            repr: HybridRepr::Sparse(Vec::new()),
+        }
+    }
+
+    /// Returns the size of the domain the set was created with.
+    pub fn domain_size(&self) -> usize {
+        self.domain_size
+    }
+
+    /// Returns true if the set has switched to its dense form.
+    pub fn is_dense(&self) -> bool {
+        match self.repr {
+            HybridRepr::Sparse(_) => false,
+            HybridRepr::Dense(_) => true,
+        }
+    }
+
+    pub fn contains(&self, bit: usize) -> bool {
+        assert!(bit < self.domain_size, "bit {} out of bounds", bit);
+        match self.repr {
+            HybridRepr::Sparse(ref elems) => elems.binary_search(&bit).is_ok(),
+            HybridRepr::Dense(ref dense) => dense.contains(bit),
+        }
+    }
+
+    /// Returns true if the bit has changed.
+    pub fn insert(&mut self, bit: usize) -> bool {
+        assert!(bit < self.domain_size, "bit {} out of bounds", bit);
+        let dense = match self.repr {
+            HybridRepr::Sparse(ref mut elems) => match elems.binary_search(&bit) {
+                Ok(_) => return false,
+                Err(index) if elems.len() < SPARSE_MAX => {
+                    elems.insert(index, bit);
+                    return true;
+                }
+                Err(_) => {
+                    let mut dense = BitVector::new(self.domain_size);
+                    for &elem in elems.iter() {
+                        dense.insert(elem);
+                    }
+                    dense
+                }
+            },
+            HybridRepr::Dense(ref mut dense) => return dense.insert(bit),
+        };
+        self.repr = HybridRepr::Dense(dense);
+        self.insert(bit)
+    }
+
+    /// Returns true if the bit has changed.
+    pub fn remove(&mut self, bit: usize) -> bool {
+        assert!(bit < self.domain_size, "bit {} out of bounds", bit);
+        match self.repr {
+            HybridRepr::Sparse(ref mut elems) => match elems.binary_search(&bit) {
+                Ok(index) => {
+                    elems.remove(index);
+                    true
+                }
+                Err(_) => false,
+            },
+            HybridRepr::Dense(ref mut dense) => dense.remove(bit),
+        }
+    }
+
+    /// Removes every bit, keeping the domain size.
+    pub fn clear(&mut self) {
+        match self.repr {
+            HybridRepr::Sparse(ref mut elems) => elems.clear(),
+            HybridRepr::Dense(ref mut dense) => dense.clear(),
+        }
+    }
+
+    /// Returns the number of bits that are set.
+    pub fn count(&self) -> usize {
+        match self.repr {
+            HybridRepr::Sparse(ref elems) => elems.len(),
+            HybridRepr::Dense(ref dense) => dense.count(),
+        }
+    }
+
+    /// Returns true if no bit is set.
+    pub fn is_empty(&self) -> bool {
+        match self.repr {
+            HybridRepr::Sparse(ref elems) => elems.is_empty(),
+            HybridRepr::Dense(ref dense) => dense.is_empty(),
+        }
+    }
+
+    /// Adds every bit of `other` to `self`. Returns true if anything
+    /// changed. Both sets must have the same domain size.
+    pub fn union(&mut self, other: &HybridBitSet) -> bool {
+        assert_eq!(self.domain_size, other.domain_size);
+        match other.repr {
+            HybridRepr::Sparse(ref elems) => {
+                let mut changed = false;
+                for &elem in elems {
+                    changed |= self.insert(elem);
+                }
+                changed
+            }
+            HybridRepr::Dense(ref dense) => self.union_dense(dense),
+        }
+    }
+
+    /// Adds every bit of the dense `other` to `self`, switching `self`
+    /// to its dense form. Returns true if anything changed. `other`
+    /// must have been created for the same domain size, and have no bits
+    /// set outside of it.
+    pub fn union_dense(&mut self, other: &BitVector) -> bool {
+        assert_eq!(other.capacity(), u64s(self.domain_size) * 64);
+        if let Some(&last) = other.words().last() {
+            // Only the last word can hold bits past the domain.
+            let used = self.domain_size - (other.capacity() - 64);
+            assert!(
+                used == 64 || last >> used == 0,
+                "bit set outside of a domain of size {}",
+                self.domain_size
+            );
+        }
+        let dense = match self.repr {
+            HybridRepr::Sparse(ref elems) => {
+                if other.iter().all(|bit| elems.binary_search(&bit).is_ok()) {
+                    return false;
+                }
+                let mut dense = other.clone();
+                for &elem in elems {
+                    dense.insert(elem);
+                }
+                dense
+            }
+            HybridRepr::Dense(ref mut dense) => return dense.union(other),
+        };
+        self.repr = HybridRepr::Dense(dense);
+        true
+    }
+
+    /// Returns a dense copy of the set.
+    pub fn to_dense(&self) -> BitVector {
+        match self.repr {
+            HybridRepr::Sparse(ref elems) => {
+                let mut dense = BitVector::new(self.domain_size);
+                for &elem in elems {
+                    dense.insert(elem);
+                }
+                dense
+            }
+            HybridRepr::Dense(ref dense) => dense.clone(),
+        }
+    }
+
+    /// Iterates over indexes of set bits in a sorted order
+    pub fn iter(&self) -> HybridIter<'_> {
+        match self.repr {
+            HybridRepr::Sparse(ref elems) => HybridIter::Sparse(elems.iter()),
+            HybridRepr::Dense(ref dense) => HybridIter::Dense(dense.iter()),
+        }
+    }
+}
+
+/// Converts a dense vector into a (dense) hybrid set whose domain is
+/// the capacity of the vector.
+impl From<BitVector> for HybridBitSet {
+    fn from(dense: BitVector) -> HybridBitSet {
+        HybridBitSet {
+            domain_size: dense.capacity(),
+            repr: HybridRepr::Dense(dense),
+        }
+    }
+}
+
+/// Iterator over the set bits of a `HybridBitSet`.
+pub enum HybridIter<'a> {
+    Sparse(slice::Iter<'a, usize>),
+    Dense(BitVectorIter<'a>),
+}
+
+impl<'a> Iterator for HybridIter<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        match *self {
+            HybridIter::Sparse(ref mut iter) => iter.next().cloned(),
+            HybridIter::Dense(ref mut iter) => iter.next(),
+        }
+    }
+}
+
+/// A matrix of bits with `num_columns` columns and any number of rows,
+/// where each row is a `HybridBitSet` that is only allocated once a bit
+/// is inserted into it. Unlike `BitMatrix`, the memory used is
+/// proportional to the number of bits set rather than to the square of
+/// the number of elements.
+#[derive(Clone, Debug)]
+pub struct SparseBitMatrix {
+    num_columns: usize,
+    rows: Vec<Option<HybridBitSet>>,
+}
+
+impl SparseBitMatrix {
+    /// Creates an empty matrix with `num_columns` columns.
+    pub fn new(num_columns: usize) -> SparseBitMatrix {
+        SparseBitMatrix {
+            num_columns,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Returns the number of columns the matrix was created with.
+    pub fn num_columns(&self) -> usize {
+        self.num_columns
+    }
+
+    /// Returns one more than the highest row that has been allocated.
+    pub fn num_rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn ensure_row(&mut self, row: usize) -> &mut HybridBitSet {
+        if row >= self.rows.len() {
+            self.rows.resize(row + 1, None);
+        }
+        let num_columns = self.num_columns;
+        self.rows[row].get_or_insert_with(|| HybridBitSet::new(num_columns))
+    }
+
+    /// Sets the bit `(row, column)`, allocating the row if needed.
+    /// Returns true if the bit has changed.
+    pub fn insert(&mut self, row: usize, column: usize) -> bool {
+        self.ensure_row(row).insert(column)
+    }
+
+    /// Removes the bit `(row, column)`. Returns true if it was set.
+    pub fn remove(&mut self, row: usize, column: usize) -> bool {
+        match self.rows.get_mut(row) {
+            Some(&mut Some(ref mut set)) => set.remove(column),
+            _ => false,
+        }
+    }
+
+    /// Do the bits from `row` contain `column`?
+    pub fn contains(&self, row: usize, column: usize) -> bool {
+        self.row(row).is_some_and(|set| set.contains(column))
+    }
+
+    /// Returns the set of bits in `row`, or `None` if nothing was ever
+    /// inserted into it.
+    pub fn row(&self, row: usize) -> Option<&HybridBitSet> {
+        self.rows.get(row).and_then(|set| set.as_ref())
+    }
+
+    /// Adds the bits from `read` to the bits from `write`, returning
+    /// true if anything changed. Same as `BitMatrix::merge`.
+    pub fn union_rows(&mut self, read: usize, write: usize) -> bool {
+        if read == write || self.row(read).is_none() {
+            return false;
         }
+        // Take the written row out, so that the read row can be
+        // borrowed at the same time.
+        self.ensure_row(write);
+        let mut write_row = self.rows[write].take().unwrap();
+        let changed = write_row.union(self.row(read).unwrap());
+        self.rows[write] = Some(write_row);
         changed
     }
+
+    /// Adds the bits of the dense `set` to `row`, returning true if
+    /// anything changed.
+    pub fn union_row_with(&mut self, set: &BitVector, row: usize) -> bool {
+        self.ensure_row(row).union_dense(set)
+    }
+
+    /// Iterates over the columns set in `row`, in sorted order.
+    pub fn iter(&self, row: usize) -> impl Iterator<Item = usize> + '_ {
+        self.row(row).into_iter().flat_map(|set| set.iter())
+    }
+
+    /// Returns the number of bits set in `row`.
+    pub fn count(&self, row: usize) -> usize {
+        self.row(row).map_or(0, |set| set.count())
+    }
+
+    /// Iterates over the indexes of the rows that have been allocated.
+    pub fn rows(&self) -> impl Iterator<Item = usize> + '_ {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|&(_, set)| set.is_some())
+            .map(|(row, _)| row)
+    }
+
+    /// Returns a dense `elements x elements` copy of the matrix, where
+    /// `elements` is large enough to cover every row and column.
+    pub fn to_dense(&self) -> BitMatrix {
+        let mut dense = BitMatrix::new(self.num_columns.max(self.rows.len()));
+        for row in self.rows() {
+            for column in self.iter(row) {
+                dense.add(row, column);
+            }
+        }
+        dense
+    }
+}
+
+/// Snapshot delegate for the words of a `SnapshotBitVector` or
+/// `SnapshotBitMatrix`. Not typically used directly, except to name
+/// the undo log entries, which are `snapshot_vec::UndoLog<BitWords>`.
+#[derive(Copy, Clone, Debug)]
+pub struct BitWords;
+
+impl sv::SnapshotVecDelegate for BitWords {
+    type Value = u64;
+    type Undo = ();
+
+    fn reverse(_: &mut Vec<u64>, _: ()) {}
+}
+
+/// A `BitVector` whose changes are recorded in an undo log, so that
+/// they can be rolled back. Every modified word is logged once per
+/// change, just like an element of a `SnapshotVec`.
+pub struct SnapshotBitVector<V: sv::VecLike<BitWords> = Vec<u64>, L = VecLog<sv::UndoLog<BitWords>>>
+{
+    words: sv::SnapshotVec<BitWords, V, L>,
+}
+
+/// A `SnapshotBitVector` without an undo log of its own; call
+/// `with_log` to modify it.
+pub type SnapshotBitVectorStorage = SnapshotBitVector<Vec<u64>, ()>;
+
+// HACK(eddyb) manual impl avoids `Default` bound on `V`.
+impl<V: sv::VecLike<BitWords> + Default, L: Default> Default for SnapshotBitVector<V, L> {
+    fn default() -> Self {
+        SnapshotBitVector {
+            words: sv::SnapshotVec::new(),
+        }
+    }
+}
+
+impl<L: Default> SnapshotBitVector<Vec<u64>, L> {
+    pub fn new(num_bits: usize) -> Self {
+        SnapshotBitVector {
+            words: sv::SnapshotVec::from(vec![0; u64s(num_bits)]),
+        }
+    }
+}
+
+impl SnapshotBitVectorStorage {
+    /// Creates a `SnapshotBitVector` using the `undo_log`, allowing
+    /// mutating methods to be called.
+    pub fn with_log<L>(&mut self, undo_log: L) -> SnapshotBitVector<&mut Vec<u64>, L>
+    where
+        L: UndoLogs<sv::UndoLog<BitWords>>,
+    {
+        SnapshotBitVector {
+            words: self.words.with_log(undo_log),
+        }
+    }
+}
+
+impl Rollback<sv::UndoLog<BitWords>> for SnapshotBitVectorStorage {
+    fn reverse(&mut self, undo: sv::UndoLog<BitWords>) {
+        self.words.reverse(undo);
+    }
+}
+
+impl<V: sv::VecLike<BitWords>, L> SnapshotBitVector<V, L> {
+    pub fn contains(&self, bit: usize) -> bool {
+        let (word, mask) = word_mask(bit);
+        (self.words[word] & mask) != 0
+    }
+
+    /// Returns the number of bits that are set.
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns true if no bit is set.
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// Returns the number of bits this vector can hold.
+    pub fn capacity(&self) -> usize {
+        self.words.len() * 64
+    }
+
+    /// Returns the underlying words, as in `BitVector::words`.
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Iterates over indexes of set bits in a sorted order
+    pub fn iter(&self) -> BitVectorIter<'_> {
+        BitVectorIter::new(&self.words)
+    }
+
+    /// Copies the current bits into a plain `BitVector`.
+    pub fn to_bit_vector(&self) -> BitVector {
+        BitSet {
+            data: self.words.to_vec(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<V: sv::VecLike<BitWords>, L: UndoLogs<sv::UndoLog<BitWords>>> SnapshotBitVector<V, L> {
+    fn set_word(&mut self, index: usize, word: u64) -> bool {
+        let changed = self.words[index] != word;
+        if changed {
+            self.words.set(index, word);
+        }
+        changed
+    }
+
+    /// Returns true if the bit has changed.
+    pub fn insert(&mut self, bit: usize) -> bool {
+        let (word, mask) = word_mask(bit);
+        let value = self.words[word] | mask;
+        self.set_word(word, value)
+    }
+
+    /// Returns true if the bit has changed.
+    pub fn remove(&mut self, bit: usize) -> bool {
+        let (word, mask) = word_mask(bit);
+        let value = self.words[word] & !mask;
+        self.set_word(word, value)
+    }
+
+    /// Removes every bit, keeping the capacity.
+    pub fn clear(&mut self) {
+        for index in 0..self.words.len() {
+            self.set_word(index, 0);
+        }
+    }
+
+    /// Adds every bit of `other` to `self`. Returns true if anything
+    /// changed.
+    pub fn union(&mut self, other: &BitVector) -> bool {
+        self.bitwise(other, |a, b| a | b)
+    }
+
+    /// Removes from `self` every bit that is not in `other`. Returns
+    /// true if anything changed.
+    pub fn intersect(&mut self, other: &BitVector) -> bool {
+        self.bitwise(other, |a, b| a & b)
+    }
+
+    /// Removes from `self` every bit that is in `other`. Returns true
+    /// if anything changed.
+    pub fn subtract(&mut self, other: &BitVector) -> bool {
+        self.bitwise(other, |a, b| a & !b)
+    }
+
+    fn bitwise(&mut self, other: &BitVector, op: impl Fn(u64, u64) -> u64) -> bool {
+        assert_eq!(self.words.len(), other.data.len());
+        let mut changed = false;
+        for (index, &word) in other.data.iter().enumerate() {
+            let value = op(self.words[index], word);
+            changed |= self.set_word(index, value);
+        }
+        changed
+    }
+
+    /// Makes room for at least `num_bits` bits. New bits are unset,
+    /// and rolling back removes them again.
+    pub fn grow(&mut self, num_bits: usize) {
+        while self.words.len() < u64s(num_bits) {
+            self.words.push(0);
+        }
+    }
+}
+
+impl<V, L> SnapshotBitVector<V, L>
+where
+    V: sv::VecLike<BitWords>,
+    L: Snapshots<sv::UndoLog<BitWords>>,
+{
+    /// Starts a new snapshot. Each snapshot must be either
+    /// rolled back or committed in a "LIFO" (stack) order.
+    pub fn start_snapshot(&mut self) -> sv::Snapshot<L::Snapshot> {
+        self.words.start_snapshot()
+    }
+
+    /// Reverses all changes since the last snapshot.
+    pub fn rollback_to(&mut self, snapshot: sv::Snapshot<L::Snapshot>) {
+        self.words.rollback_to(snapshot);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: sv::Snapshot<L::Snapshot>) {
+        self.words.commit(snapshot);
+    }
+}
+
+/// A `BitMatrix` whose changes are recorded in an undo log, so that
+/// they can be rolled back. Unlike `BitMatrix`, its size is fixed.
+pub struct SnapshotBitMatrix<V: sv::VecLike<BitWords> = Vec<u64>, L = VecLog<sv::UndoLog<BitWords>>>
+{
+    elements: usize,
+    words: sv::SnapshotVec<BitWords, V, L>,
+}
+
+/// A `SnapshotBitMatrix` without an undo log of its own; call
+/// `with_log` to modify it.
+pub type SnapshotBitMatrixStorage = SnapshotBitMatrix<Vec<u64>, ()>;
+
+impl<L: Default> SnapshotBitMatrix<Vec<u64>, L> {
+    // Create a new `elements x elements` matrix, initially empty.
+    pub fn new(elements: usize) -> Self {
+        SnapshotBitMatrix {
+            elements,
+            words: sv::SnapshotVec::from(vec![0; elements * u64s(elements)]),
+        }
+    }
+}
+
+impl SnapshotBitMatrixStorage {
+    /// Creates a `SnapshotBitMatrix` using the `undo_log`, allowing
+    /// mutating methods to be called.
+    pub fn with_log<L>(&mut self, undo_log: L) -> SnapshotBitMatrix<&mut Vec<u64>, L>
+    where
+        L: UndoLogs<sv::UndoLog<BitWords>>,
+    {
+        SnapshotBitMatrix {
+            elements: self.elements,
+            words: self.words.with_log(undo_log),
+        }
+    }
+}
+
+impl Rollback<sv::UndoLog<BitWords>> for SnapshotBitMatrixStorage {
+    fn reverse(&mut self, undo: sv::UndoLog<BitWords>) {
+        self.words.reverse(undo);
+    }
+}
+
+impl<V: sv::VecLike<BitWords>, L> SnapshotBitMatrix<V, L> {
+    fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Returns `N`, the number of rows (and columns).
+    pub fn elements(&self) -> usize {
+        self.elements
+    }
+
+    /// The range of bits for a given element.
+    fn range(&self, element: usize) -> (usize, usize) {
+        assert!(element < self.elements, "row {} out of bounds", element);
+        let u64s_per_elem = u64s(self.elements);
+        let start = element * u64s_per_elem;
+        (start, start + u64s_per_elem)
+    }
+
+    fn word_mask(&self, target: usize) -> (usize, u64) {
+        assert!(target < self.elements, "column {} out of bounds", target);
+        word_mask(target)
+    }
+
+    /// Do the bits from `source` contain `target`?
+    pub fn contains(&self, source: usize, target: usize) -> bool {
+        let (start, _) = self.range(source);
+        let (word, mask) = self.word_mask(target);
+        (self.words[start + word] & mask) != 0
+    }
+
+    /// Returns the words of row `source`, in the same layout as
+    /// `BitVector::words`.
+    pub fn row(&self, source: usize) -> &[u64] {
+        let (start, end) = self.range(source);
+        &self.words()[start..end]
+    }
+
+    /// Iterates over the columns set in row `source`, in sorted order.
+    pub fn iter(&self, source: usize) -> BitVectorIter<'_> {
+        BitVectorIter::new(self.row(source))
+    }
+
+    /// Returns the number of bits set in row `source`.
+    pub fn count(&self, source: usize) -> usize {
+        self.row(source)
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+}
+
+impl<V: sv::VecLike<BitWords>, L: UndoLogs<sv::UndoLog<BitWords>>> SnapshotBitMatrix<V, L> {
+    fn set_word(&mut self, index: usize, word: u64) -> bool {
+        let changed = self.words[index] != word;
+        if changed {
+            self.words.set(index, word);
+        }
+        changed
+    }
+
+    pub fn add(&mut self, source: usize, target: usize) -> bool {
+        let (start, _) = self.range(source);
+        let (word, mask) = self.word_mask(target);
+        let value = self.words[start + word] | mask;
+        self.set_word(start + word, value)
+    }
+
+    /// Removes the bit `(source, target)`. Returns true if it was set.
+    pub fn remove(&mut self, source: usize, target: usize) -> bool {
+        let (start, _) = self.range(source);
+        let (word, mask) = self.word_mask(target);
+        let value = self.words[start + word] & !mask;
+        self.set_word(start + word, value)
+    }
+
+    /// Add the bits from `read` to the bits from `write`,
+    /// return true if anything changed.
+    pub fn merge(&mut self, read: usize, write: usize) -> bool {
+        let (read_start, read_end) = self.range(read);
+        let (write_start, _) = self.range(write);
+        let mut changed = false;
+        for (offset, read_index) in (read_start..read_end).enumerate() {
+            let value = self.words[write_start + offset] | self.words[read_index];
+            changed |= self.set_word(write_start + offset, value);
+        }
+        changed
+    }
+
+    /// Removes every bit, keeping the size.
+    pub fn clear(&mut self) {
+        for index in 0..self.words.len() {
+            self.set_word(index, 0);
+        }
+    }
+}
+
+impl<V, L> SnapshotBitMatrix<V, L>
+where
+    V: sv::VecLike<BitWords>,
+    L: Snapshots<sv::UndoLog<BitWords>>,
+{
+    /// Starts a new snapshot. Each snapshot must be either
+    /// rolled back or committed in a "LIFO" (stack) order.
+    pub fn start_snapshot(&mut self) -> sv::Snapshot<L::Snapshot> {
+        self.words.start_snapshot()
+    }
+
+    /// Reverses all changes since the last snapshot.
+    pub fn rollback_to(&mut self, snapshot: sv::Snapshot<L::Snapshot>) {
+        self.words.rollback_to(snapshot);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: sv::Snapshot<L::Snapshot>) {
+        self.words.commit(snapshot);
+    }
 }
 
 fn u64s(elements: usize) -> usize {
-    (elements + 63) / 64
+    elements.div_ceil(64)
 }
 
 fn word_mask(index: usize) -> (usize, u64) {
@@ -208,8 +1114,10 @@ fn bitvec_iter_works() {
     bitvec.insert(65);
     bitvec.insert(66);
     bitvec.insert(99);
-    assert_eq!(bitvec.iter().collect::<Vec<_>>(),
-               [1, 10, 19, 62, 63, 64, 65, 66, 99]);
+    assert_eq!(
+        bitvec.iter().collect::<Vec<_>>(),
+        [1, 10, 19, 62, 63, 64, 65, 66, 99]
+    );
 }
 
 #[test]
@@ -222,9 +1130,10 @@ fn bitvec_iter_works_2() {
     bitvec.insert(66);
     bitvec.insert(99);
     bitvec.insert(299);
-    assert_eq!(bitvec.iter().collect::<Vec<_>>(),
-               [1, 10, 19, 62, 66, 99, 299]);
-
+    assert_eq!(
+        bitvec.iter().collect::<Vec<_>>(),
+        [1, 10, 19, 62, 66, 99, 299]
+    );
 }
 
 #[test]
@@ -255,6 +1164,48 @@ fn union_two_vecs() {
     assert!(vec1.contains(64));
 }
 
+#[test]
+fn set_operations() {
+    let mut a = BitVector::new(130);
+    let mut b = BitVector::new(130);
+    for &bit in &[1, 64, 100, 129] {
+        a.insert(bit);
+    }
+    for &bit in &[64, 129] {
+        b.insert(bit);
+    }
+    assert_eq!(a.count(), 4);
+    assert!(b.is_subset(&a));
+    assert!(!a.is_subset(&b));
+
+    let mut c = a.clone();
+    assert!(c.intersect(&b));
+    assert!(!c.intersect(&b));
+    assert_eq!(c, b);
+
+    let mut d = a.clone();
+    assert!(d.subtract(&b));
+    assert_eq!(d.iter().collect::<Vec<_>>(), [1, 100]);
+    assert!(!d.subtract(&b));
+
+    assert!(d.remove(100));
+    assert!(!d.remove(100));
+    assert_eq!(d.iter().collect::<Vec<_>>(), [1]);
+
+    d.clear();
+    assert!(d.is_empty());
+    assert_eq!(d.count(), 0);
+    assert_eq!(d.capacity(), 192);
+}
+
+#[test]
+fn words() {
+    let mut vec1 = BitVector::new(128);
+    vec1.insert(0);
+    vec1.insert(65);
+    assert_eq!(vec1.words(), [1, 2]);
+}
+
 #[test]
 fn grow() {
     let mut vec1 = BitVector::new(65);
@@ -267,6 +1218,12 @@ fn grow() {
     assert!(vec1.contains(5));
     assert!(vec1.contains(64));
     assert!(!vec1.contains(126));
+    vec1.grow(300);
+    assert!(vec1.insert(299));
+    assert_eq!(vec1.iter().collect::<Vec<_>>(), [3, 5, 64, 299]);
+    // Growing never shrinks.
+    vec1.grow(1);
+    assert!(vec1.contains(299));
 }
 
 #[test]
@@ -299,3 +1256,307 @@ fn matrix_intersection() {
     let intersection = vec1.intersection(2, 65);
     assert_eq!(intersection, &[10, 64, 160]);
 }
+
+#[test]
+fn matrix_rows() {
+    let mut matrix = BitMatrix::new(100);
+    matrix.add(3, 7);
+    matrix.add(3, 70);
+    matrix.add(4, 70);
+    assert_eq!(matrix.iter(3).collect::<Vec<_>>(), [7, 70]);
+    assert_eq!(matrix.count(3), 2);
+    assert_eq!(matrix.row(4), [0, 1 << 6]);
+    assert!(matrix.iter(5).next().is_none());
+
+    assert!(matrix.remove(3, 7));
+    assert!(!matrix.remove(3, 7));
+    assert_eq!(matrix.iter(3).collect::<Vec<_>>(), [70]);
+
+    matrix.clear();
+    assert_eq!(matrix.count(3), 0);
+    assert_eq!(matrix.count(4), 0);
+}
+
+#[test]
+fn matrix_resize() {
+    let mut matrix = BitMatrix::new(70);
+    matrix.add(0, 1);
+    matrix.add(1, 69);
+    matrix.add(69, 0);
+
+    matrix.resize(200);
+    assert_eq!(matrix.elements(), 200);
+    assert!(matrix.contains(0, 1));
+    assert!(matrix.contains(1, 69));
+    assert!(matrix.contains(69, 0));
+    assert!(matrix.add(199, 199));
+    assert_eq!(matrix.iter(1).collect::<Vec<_>>(), [69]);
+
+    matrix.resize(10);
+    assert!(matrix.contains(0, 1));
+    assert_eq!(matrix.count(1), 0);
+    assert_eq!(matrix.iter(0).collect::<Vec<_>>(), [1]);
+}
+
+#[test]
+#[should_panic]
+fn matrix_out_of_bounds() {
+    let matrix = BitMatrix::new(10);
+    matrix.contains(0, 10);
+}
+
+#[test]
+fn snapshot_bitvec_rollback() {
+    let mut bits: SnapshotBitVector = SnapshotBitVector::new(100);
+    bits.insert(3);
+
+    let snapshot = bits.start_snapshot();
+    assert!(bits.insert(70));
+    assert!(bits.remove(3));
+    let mut other = BitVector::new(100);
+    other.insert(99);
+    assert!(bits.union(&other));
+    bits.grow(200);
+    assert!(bits.insert(150));
+    assert_eq!(bits.iter().collect::<Vec<_>>(), [70, 99, 150]);
+    bits.rollback_to(snapshot);
+
+    assert_eq!(bits.iter().collect::<Vec<_>>(), [3]);
+    assert_eq!(bits.capacity(), 128);
+
+    let snapshot = bits.start_snapshot();
+    bits.clear();
+    bits.commit(snapshot);
+    assert!(bits.is_empty());
+}
+
+#[test]
+fn snapshot_bitvec_logs_changes_only() {
+    let mut bits: SnapshotBitVector = SnapshotBitVector::new(64);
+    bits.insert(1);
+    let snapshot = bits.start_snapshot();
+    assert!(!bits.insert(1));
+    assert!(!bits.remove(2));
+    assert!(bits.words.actions_since_snapshot(&snapshot).is_empty());
+    bits.commit(snapshot);
+}
+
+#[test]
+fn snapshot_bitmatrix_rollback() {
+    let mut matrix: SnapshotBitMatrix = SnapshotBitMatrix::new(70);
+    matrix.add(0, 1);
+    matrix.add(1, 69);
+
+    let snapshot = matrix.start_snapshot();
+    assert!(matrix.merge(1, 0));
+    assert!(!matrix.merge(1, 0));
+    assert_eq!(matrix.iter(0).collect::<Vec<_>>(), [1, 69]);
+    assert!(matrix.remove(1, 69));
+    matrix.rollback_to(snapshot);
+
+    assert_eq!(matrix.iter(0).collect::<Vec<_>>(), [1]);
+    assert_eq!(matrix.iter(1).collect::<Vec<_>>(), [69]);
+
+    let snapshot = matrix.start_snapshot();
+    matrix.clear();
+    matrix.commit(snapshot);
+    assert_eq!(matrix.count(0) + matrix.count(1), 0);
+}
+
+#[test]
+fn snapshot_bitvec_storage() {
+    let mut storage = SnapshotBitVectorStorage::new(64);
+    let mut log = VecLog::default();
+
+    let snapshot = log.start_snapshot();
+    storage.with_log(&mut log).insert(5);
+    storage.with_log(&mut log).grow(128);
+    assert!(storage.contains(5));
+    assert_eq!(storage.capacity(), 128);
+    log.rollback_to(|| &mut storage, snapshot);
+
+    assert!(!storage.contains(5));
+    assert_eq!(storage.capacity(), 64);
+}
+
+#[test]
+fn hybrid_bitset() {
+    let mut set = HybridBitSet::new(1_000_000);
+    assert!(set.is_empty());
+    assert!(set.insert(999_999));
+    assert!(set.insert(3));
+    assert!(!set.insert(3));
+    assert!(!set.is_dense());
+    assert_eq!(set.iter().collect::<Vec<_>>(), [3, 999_999]);
+
+    for bit in 10..SPARSE_MAX + 10 {
+        set.insert(bit);
+    }
+    assert!(set.is_dense());
+    assert_eq!(set.count(), SPARSE_MAX + 2);
+    assert!(set.contains(3));
+    assert!(set.contains(999_999));
+    assert!(!set.contains(4));
+
+    assert!(set.remove(3));
+    assert!(!set.remove(3));
+    assert_eq!(set.iter().next(), Some(10));
+    set.clear();
+    assert!(set.is_empty());
+}
+
+#[test]
+fn hybrid_union() {
+    let mut a = HybridBitSet::new(100);
+    let mut b = HybridBitSet::new(100);
+    a.insert(1);
+    b.insert(1);
+    assert!(!a.union(&b));
+    b.insert(50);
+    assert!(a.union(&b));
+    assert!(!a.is_dense());
+    assert_eq!(a.iter().collect::<Vec<_>>(), [1, 50]);
+
+    let mut dense = BitVector::new(100);
+    dense.insert(50);
+    assert!(!a.union_dense(&dense));
+    assert!(!a.is_dense());
+    dense.insert(99);
+    assert!(a.union_dense(&dense));
+    assert!(a.is_dense());
+    assert_eq!(a.to_dense().iter().collect::<Vec<_>>(), [1, 50, 99]);
+
+    let c = HybridBitSet::from(dense);
+    assert_eq!(c.domain_size(), 128);
+    assert!(c.is_dense());
+    assert_eq!(c.iter().collect::<Vec<_>>(), [50, 99]);
+}
+
+#[test]
+fn sparse_matrix() {
+    let mut matrix = SparseBitMatrix::new(1_000_000);
+    assert!(matrix.insert(10, 999_999));
+    assert!(matrix.insert(10, 5));
+    assert!(!matrix.insert(10, 5));
+    assert!(matrix.insert(3, 7));
+    assert_eq!(matrix.num_rows(), 11);
+    assert_eq!(matrix.rows().collect::<Vec<_>>(), [3, 10]);
+    assert!(matrix.row(4).is_none());
+    assert!(!matrix.contains(4, 5));
+    assert!(!matrix.contains(100, 5));
+    assert_eq!(matrix.iter(10).collect::<Vec<_>>(), [5, 999_999]);
+    assert_eq!(matrix.iter(4).count(), 0);
+
+    assert!(matrix.union_rows(10, 3));
+    assert!(!matrix.union_rows(10, 3));
+    assert!(!matrix.union_rows(4, 3));
+    assert_eq!(matrix.count(3), 3);
+    assert!(matrix.union_rows(3, 0));
+    assert_eq!(matrix.iter(0).collect::<Vec<_>>(), [5, 7, 999_999]);
+
+    assert!(matrix.remove(0, 7));
+    assert!(!matrix.remove(0, 7));
+    assert!(!matrix.remove(200, 7));
+}
+
+#[test]
+fn sparse_matrix_dense_interop() {
+    let mut matrix = SparseBitMatrix::new(4);
+    let mut set = BitVector::new(4);
+    set.insert(1);
+    set.insert(3);
+    assert!(matrix.union_row_with(&set, 2));
+    assert!(!matrix.union_row_with(&set, 2));
+    matrix.insert(0, 2);
+
+    let dense = matrix.to_dense();
+    assert_eq!(dense.elements(), 4);
+    assert_eq!(dense.iter(2).collect::<Vec<_>>(), [1, 3]);
+    assert_eq!(dense.iter(0).collect::<Vec<_>>(), [2]);
+    assert_eq!(dense.count(1), 0);
+}
+
+#[cfg(test)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Row(usize);
+
+#[cfg(test)]
+impl Idx for Row {
+    fn from_usize(index: usize) -> Row {
+        Row(index)
+    }
+    fn to_usize(self) -> usize {
+        self.0
+    }
+}
+
+#[cfg(test)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Column(u32);
+
+#[cfg(test)]
+impl ::unify::UnifyKey for Column {
+    type Value = ();
+    type Index = u32;
+    fn index(&self) -> u32 {
+        self.0
+    }
+    fn from_index(u: u32) -> Column {
+        Column(u)
+    }
+    fn tag() -> &'static str {
+        "Column"
+    }
+}
+
+#[test]
+fn typed_bitset() {
+    let mut set: BitSet<Column> = BitSet::new(100);
+    assert!(set.insert(Column(3)));
+    assert!(set.insert(Column(70)));
+    assert!(set.contains(Column(70)));
+    assert!(!set.contains(Column(4)));
+    assert_eq!(set.iter().collect::<Vec<_>>(), [Column(3), Column(70)]);
+}
+
+#[test]
+fn typed_matrix() {
+    let mut matrix: BitMatrix<Row, Column> = BitMatrix::with_size(3, 100);
+    assert_eq!(matrix.num_rows(), 3);
+    assert_eq!(matrix.num_columns(), 100);
+    assert!(matrix.add(Row(0), Column(99)));
+    assert!(matrix.add(Row(1), Column(99)));
+    assert!(matrix.add(Row(1), Column(5)));
+    assert_eq!(matrix.intersection(Row(0), Row(1)), [Column(99)]);
+    assert!(matrix.merge(Row(1), Row(2)));
+    assert_eq!(
+        matrix.iter(Row(2)).collect::<Vec<_>>(),
+        [Column(5), Column(99)]
+    );
+
+    matrix.resize_to(4, 64);
+    assert_eq!(matrix.iter(Row(2)).collect::<Vec<_>>(), [Column(5)]);
+    assert_eq!(matrix.count(Row(3)), 0);
+}
+
+#[test]
+fn matrix_resize_to_zero_columns() {
+    let mut matrix: BitMatrix = BitMatrix::new(10);
+    assert!(matrix.add(3, 7));
+    matrix.resize_to(5, 0);
+    assert_eq!(matrix.num_rows(), 5);
+    assert_eq!(matrix.num_columns(), 0);
+    assert_eq!(matrix.count(3), 0);
+
+    matrix.resize_to(5, 8);
+    assert!(!matrix.contains(3, 7));
+}
+
+#[test]
+#[should_panic(expected = "outside of a domain")]
+fn hybrid_union_dense_outside_domain() {
+    let mut set = HybridBitSet::new(10);
+    let mut dense = BitVector::new(10);
+    dense.insert(60);
+    set.union_dense(&dense);
+}
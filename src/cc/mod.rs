@@ -0,0 +1,336 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An implementation of the Congruence Closure algorithm based on the
+//! paper "Fast Decision Procedures Based on Congruence Closure" by
+//! Nelson and Oppen, JACM 1980.
+//!
+//! The main type is `CongruenceClosure`. You define your own type of
+//! *keys* (terms) and implement the `Key` trait for it; a key is
+//! typically something like a function application `f(a, b)`, whose
+//! *successors* are its arguments `a` and `b`. Once two keys have
+//! been merged, any two applications whose heads are equal and whose
+//! arguments are pairwise merged are merged as well. So if you merge
+//! `a` and `c`, then `f(a, b)` and `f(c, b)` become equivalent.
+//!
+//! Equivalence classes are tracked with a `UnificationTable`, so
+//! a `CongruenceClosure` supports the same `snapshot`/`rollback_to`/
+//! `commit` discipline: snapshots must be consumed in LIFO order.
+
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use unify::{self as ut, InPlace, InPlaceUnificationTable, UnifyKey};
+
+#[cfg(test)]
+mod tests;
+
+/// Trait implemented by the terms stored in a `CongruenceClosure`.
+pub trait Key: Hash + Eq + Clone + Debug {
+    /// Compares the local data of two keys, not considering their
+    /// successors. So e.g. `f(a, b)` and `f(c, d)` are shallow equal
+    /// for any `a`, `b`, `c` and `d`, but `f(a)` and `g(a)` are not.
+    /// Shallow equal keys must have the same number of successors.
+    fn shallow_eq(&self, key: &Self) -> bool;
+
+    /// Returns the immediate subterms of this key, in order. For a
+    /// function application `f(a, b)`, this would be `[a, b]`.
+    fn successors(&self) -> Vec<Self>;
+}
+
+/// Every key added to a `CongruenceClosure` is assigned a token. The
+/// token's index is used both for the unification table and for the
+/// term graph, since every node has a slot in both.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Token {
+    index: u32,
+}
+
+impl Token {
+    fn from_node(node: NodeIndex) -> Token {
+        Token {
+            index: node.index() as u32,
+        }
+    }
+
+    fn node(&self) -> NodeIndex {
+        NodeIndex::new(self.index as usize)
+    }
+}
+
+impl UnifyKey for Token {
+    type Value = ();
+    type Index = u32;
+    fn index(&self) -> u32 {
+        self.index
+    }
+    fn from_index(i: u32) -> Token {
+        Token { index: i }
+    }
+    fn tag() -> &'static str {
+        "CongruenceClosure"
+    }
+}
+
+/// The congruence closure of a set of keys. Edges in the graph go
+/// from a key to each of its successors and are labeled with the
+/// position of the successor.
+pub struct CongruenceClosure<K: Key> {
+    map: HashMap<K, Token>,
+    table: InPlaceUnificationTable<Token>,
+    graph: Graph<K, usize>,
+}
+
+/// At any time, users may snapshot a congruence closure. The keys
+/// added and merges made during the snapshot may either be
+/// *committed* or *rolled back*.
+pub struct Snapshot {
+    snapshot: ut::Snapshot<InPlace<Token>>,
+}
+
+impl<K: Key> Default for CongruenceClosure<K> {
+    fn default() -> Self {
+        CongruenceClosure {
+            map: HashMap::new(),
+            table: InPlaceUnificationTable::new(),
+            graph: Graph::new(),
+        }
+    }
+}
+
+impl<K: Key> CongruenceClosure<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of keys added so far, including keys that
+    /// were added implicitly as successors of other keys.
+    pub fn len(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    /// Returns true if no keys have been added yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the key for a given token.
+    pub fn key(&self, token: Token) -> &K {
+        &self.graph[token.node()]
+    }
+
+    /// Returns the token for `key`, if it has been added.
+    pub fn token(&self, key: &K) -> Option<Token> {
+        self.map.get(key).cloned()
+    }
+
+    /// Adds a key (and, recursively, its successors) into the
+    /// congruence closure, returning the corresponding token. If the
+    /// new key is congruent to some existing key, the two are merged.
+    pub fn add(&mut self, key: K) -> Token {
+        if let Some(token) = self.token(&key) {
+            return token;
+        }
+
+        debug!("add(): key={:?}", key);
+
+        // Add the successors first: if we are adding `f(a, b)`, we
+        // need tokens for `a` and `b`.
+        let successors: Vec<Token> = key.successors().into_iter().map(|s| self.add(s)).collect();
+
+        let token = self.table.new_key(());
+        let node = self.graph.add_node(key.clone());
+        debug_assert_eq!(token.node(), node);
+        self.map.insert(key, token);
+
+        for (position, &successor) in successors.iter().enumerate() {
+            self.graph.add_edge(node, successor.node(), position);
+        }
+
+        // The arguments may already be merged with the arguments of
+        // some existing application. For example, if we have
+        //
+        //     f(b) -> b == a
+        //
+        // and we just added `f(a)`, then `f(a)` and `f(b)` must be
+        // merged. Any such application is a predecessor of something
+        // in the class of one of our successors.
+        let mut candidates = vec![];
+        for &successor in &successors {
+            candidates.extend(self.algorithm().all_preds(successor));
+        }
+        for candidate in candidates {
+            if candidate != token {
+                self.algorithm().maybe_merge(token, candidate);
+            }
+        }
+
+        token
+    }
+
+    /// Indicates that `key1` and `key2` are equivalent. Any keys that
+    /// are congruent as a result are merged as well.
+    pub fn merge(&mut self, key1: K, key2: K) {
+        let token1 = self.add(key1);
+        let token2 = self.add(key2);
+        self.algorithm().merge(token1, token2);
+    }
+
+    /// Indicates whether `key1` and `key2` are equivalent.
+    pub fn merged(&mut self, key1: K, key2: K) -> bool {
+        // Careful: you cannot skip the `add` calls here. If we merge
+        // `a` and `b` and then ask whether `f(a)` and `f(b)` are
+        // merged, the answer is only found by adding both terms and
+        // letting congruence do its work.
+        debug!("merged({:?}, {:?})", key1, key2);
+
+        let token1 = self.add(key1);
+        let token2 = self.add(key2);
+        self.table.unioned(token1, token2)
+    }
+
+    /// Returns the token representing the class of `key`.
+    pub fn find(&mut self, key: K) -> Token {
+        let token = self.add(key);
+        self.table.find(token)
+    }
+
+    /// Starts a new snapshot. Each snapshot must be either
+    /// rolled back or committed in a "LIFO" (stack) order.
+    pub fn snapshot(&mut self) -> Snapshot {
+        Snapshot {
+            snapshot: self.table.snapshot(),
+        }
+    }
+
+    /// Reverses all merges since the last snapshot. Also removes any
+    /// keys that have been added since then.
+    pub fn rollback_to(&mut self, snapshot: Snapshot) {
+        debug!("{}: rollback_to()", Token::tag());
+        let new_tokens = self.table.vars_since_snapshot(&snapshot.snapshot);
+        for index in (new_tokens.start.index..new_tokens.end.index).rev() {
+            // Nodes are removed from the end, so no other node is
+            // moved into the removed node's index.
+            let key = self.graph.remove_node(NodeIndex::new(index as usize));
+            self.map.remove(&key.unwrap());
+        }
+        self.table.rollback_to(snapshot.snapshot);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: Snapshot) {
+        debug!("{}: commit()", Token::tag());
+        self.table.commit(snapshot.snapshot);
+    }
+
+    fn algorithm(&mut self) -> Algorithm<'_, K> {
+        Algorithm {
+            graph: &self.graph,
+            table: &mut self.table,
+        }
+    }
+}
+
+// # The core algorithm
+
+struct Algorithm<'a, K: 'a> {
+    graph: &'a Graph<K, usize>,
+    table: &'a mut InPlaceUnificationTable<Token>,
+}
+
+impl<'a, K: Key> Algorithm<'a, K> {
+    fn merge(&mut self, u: Token, v: Token) {
+        debug!("merge(): u={:?} v={:?}", self.key(u), self.key(v));
+
+        if self.table.unioned(u, v) {
+            return;
+        }
+
+        let u_preds = self.all_preds(u);
+        let v_preds = self.all_preds(v);
+
+        self.table.union(u, v);
+
+        for &p_u in &u_preds {
+            for &p_v in &v_preds {
+                self.maybe_merge(p_u, p_v);
+            }
+        }
+    }
+
+    /// Returns the predecessors of every token in the class of `u`.
+    fn all_preds(&mut self, u: Token) -> Vec<Token> {
+        let graph = self.graph;
+        self.table
+            .class_members(u)
+            .flat_map(|k| graph.neighbors_directed(k.node(), Direction::Incoming))
+            .map(Token::from_node)
+            .collect()
+    }
+
+    fn maybe_merge(&mut self, p_u: Token, p_v: Token) {
+        debug!(
+            "maybe_merge(): p_u={:?} p_v={:?}",
+            self.key(p_u),
+            self.key(p_v)
+        );
+
+        if !self.table.unioned(p_u, p_v) && self.shallow_eq(p_u, p_v) && self.congruent(p_u, p_v) {
+            self.merge(p_u, p_v);
+        }
+    }
+
+    // Check whether each of the successors are unioned. So if you
+    // have `f(x1)` and `f(x2)`, this is true if `x1 == x2`. (The
+    // result of this fn is not really meaningful unless the two nodes
+    // are shallow equal here.)
+    fn congruent(&mut self, p_u: Token, p_v: Token) -> bool {
+        let succs_u = self.successors(p_u);
+        let succs_v = self.successors(p_v);
+        let r = succs_u.len() == succs_v.len()
+            && succs_u
+                .into_iter()
+                .zip(succs_v)
+                .all(|(s_u, s_v)| self.table.unioned(s_u, s_v));
+        debug!(
+            "congruent({:?}, {:?}) = {:?}",
+            self.key(p_u),
+            self.key(p_v),
+            r
+        );
+        r
+    }
+
+    fn key(&self, u: Token) -> &'a K {
+        &self.graph[u.node()]
+    }
+
+    // Compare the local data, not considering successor nodes. So e.g
+    // `f(x)` and `f(y)` are shallow equal for any `x` and `y`.
+    fn shallow_eq(&self, u: Token, v: Token) -> bool {
+        self.key(u).shallow_eq(self.key(v))
+    }
+
+    /// Returns the successors of `token`, ordered by position.
+    fn successors(&self, token: Token) -> Vec<Token> {
+        let mut edges: Vec<(usize, Token)> = self
+            .graph
+            .edges_directed(token.node(), Direction::Outgoing)
+            .map(|edge| (*edge.weight(), Token::from_node(edge.target())))
+            .collect();
+        edges.sort_by_key(|&(position, _)| position);
+        edges.into_iter().map(|(_, token)| token).collect()
+    }
+}
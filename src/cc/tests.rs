@@ -0,0 +1,152 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use cc::{CongruenceClosure, Key};
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum Term {
+    Const(&'static str),
+    Apply(&'static str, Vec<Term>),
+}
+
+use self::Term::*;
+
+impl Key for Term {
+    fn shallow_eq(&self, key: &Term) -> bool {
+        match (self, key) {
+            (Const(a), Const(b)) => a == b,
+            (Apply(f, a_args), Apply(g, b_args)) => f == g && a_args.len() == b_args.len(),
+            _ => false,
+        }
+    }
+
+    fn successors(&self) -> Vec<Term> {
+        match self {
+            Const(_) => vec![],
+            Apply(_, args) => args.clone(),
+        }
+    }
+}
+
+fn c(name: &'static str) -> Term {
+    Const(name)
+}
+
+fn f(args: Vec<Term>) -> Term {
+    Apply("f", args)
+}
+
+fn g(args: Vec<Term>) -> Term {
+    Apply("g", args)
+}
+
+#[test]
+fn simple_as_it_gets() {
+    let mut cc: CongruenceClosure<Term> = CongruenceClosure::new();
+    assert!(cc.merged(c("a"), c("a")));
+    assert!(!cc.merged(c("a"), c("b")));
+    cc.merge(c("a"), c("b"));
+    assert!(cc.merged(c("a"), c("b")));
+    assert!(cc.merged(c("b"), c("a")));
+}
+
+#[test]
+fn merge_then_add() {
+    let mut cc: CongruenceClosure<Term> = CongruenceClosure::new();
+    cc.add(f(vec![c("a")]));
+    cc.merge(c("a"), c("b"));
+    // `f(b)` did not exist at merge time but is found congruent on add.
+    assert!(cc.merged(f(vec![c("a")]), f(vec![c("b")])));
+    assert!(!cc.merged(g(vec![c("a")]), f(vec![c("b")])));
+}
+
+#[test]
+fn add_then_merge() {
+    let mut cc: CongruenceClosure<Term> = CongruenceClosure::new();
+    cc.add(f(vec![c("a"), c("x")]));
+    cc.add(f(vec![c("b"), c("x")]));
+    cc.add(f(vec![c("b"), c("y")]));
+    cc.merge(c("a"), c("b"));
+    assert!(cc.merged(f(vec![c("a"), c("x")]), f(vec![c("b"), c("x")])));
+    assert!(!cc.merged(f(vec![c("a"), c("x")]), f(vec![c("b"), c("y")])));
+}
+
+#[test]
+fn argument_order_matters() {
+    let mut cc: CongruenceClosure<Term> = CongruenceClosure::new();
+    cc.merge(c("a"), c("b"));
+    assert!(!cc.merged(f(vec![c("a"), c("x")]), f(vec![c("x"), c("b")])));
+    assert!(cc.merged(f(vec![c("x"), c("a")]), f(vec![c("x"), c("b")])));
+}
+
+#[test]
+fn nested_propagation() {
+    // a == b implies f(f(a)) == f(f(b)).
+    let mut cc: CongruenceClosure<Term> = CongruenceClosure::new();
+    cc.add(f(vec![f(vec![c("a")])]));
+    cc.add(f(vec![f(vec![c("b")])]));
+    assert!(!cc.merged(f(vec![f(vec![c("a")])]), f(vec![f(vec![c("b")])])));
+    cc.merge(c("a"), c("b"));
+    assert!(cc.merged(f(vec![f(vec![c("a")])]), f(vec![f(vec![c("b")])])));
+}
+
+#[test]
+fn transitive_through_class() {
+    // f(a) == c, a == b, so f(b) == c.
+    let mut cc: CongruenceClosure<Term> = CongruenceClosure::new();
+    cc.merge(f(vec![c("a")]), c("c"));
+    cc.merge(c("a"), c("b"));
+    assert!(cc.merged(f(vec![c("b")]), c("c")));
+}
+
+#[test]
+fn fixpoint() {
+    // The classic: f(f(f(a))) == a and f(f(f(f(f(a))))) == a imply
+    // f(a) == a.
+    let mut cc: CongruenceClosure<Term> = CongruenceClosure::new();
+    let mut terms = vec![c("a")];
+    for i in 0..5 {
+        let t = f(vec![terms[i].clone()]);
+        terms.push(t);
+    }
+    cc.merge(terms[3].clone(), terms[0].clone());
+    cc.merge(terms[5].clone(), terms[0].clone());
+    assert!(cc.merged(terms[1].clone(), terms[0].clone()));
+}
+
+#[test]
+fn rollback() {
+    let mut cc: CongruenceClosure<Term> = CongruenceClosure::new();
+    cc.add(f(vec![c("a")]));
+    let len = cc.len();
+
+    let snapshot = cc.snapshot();
+    cc.merge(c("a"), c("b"));
+    assert!(cc.merged(f(vec![c("a")]), f(vec![c("b")])));
+    cc.rollback_to(snapshot);
+
+    assert_eq!(cc.len(), len);
+    assert!(cc.token(&c("b")).is_none());
+    assert!(!cc.merged(f(vec![c("a")]), f(vec![c("b")])));
+    assert!(!cc.merged(c("a"), c("b")));
+}
+
+#[test]
+fn nested_commit_then_rollback() {
+    let mut cc: CongruenceClosure<Term> = CongruenceClosure::new();
+    let outer = cc.snapshot();
+    let inner = cc.snapshot();
+    cc.merge(c("a"), c("b"));
+    cc.commit(inner);
+    assert!(cc.merged(f(vec![c("a")]), f(vec![c("b")])));
+    cc.rollback_to(outer);
+    assert!(cc.is_empty());
+    assert!(!cc.merged(f(vec![c("a")]), f(vec![c("b")])));
+}
@@ -0,0 +1,43 @@
+//! Typed indices. `Idx` is implemented by types that can be converted
+//! to and from a `usize` index, so that index-based containers such as
+//! `bitvec::BitSet` or `snapshot_vec::IndexedSnapshotVec` can be keyed
+//! by a specific type, and keys meant for one table cannot be used by
+//! mistake with another.
+//!
+//! Every `UnifyKey` is an `Idx`, using `UnifyKey::index` and
+//! `UnifyKey::from_index`.
+
+use std::fmt::Debug;
+
+use unify::{self, UnifyIndex, UnifyKey};
+
+/// A type that can be used as an index into a typed container.
+///
+/// The method names differ from the ones of `UnifyKey` and `UnifyIndex`
+/// on purpose, so that calls stay unambiguous when these traits are in
+/// scope together.
+pub trait Idx: Copy + Debug + PartialEq {
+    fn from_usize(index: usize) -> Self;
+
+    fn to_usize(self) -> usize;
+}
+
+impl Idx for usize {
+    fn from_usize(index: usize) -> usize {
+        index
+    }
+
+    fn to_usize(self) -> usize {
+        self
+    }
+}
+
+impl<K: UnifyKey> Idx for K {
+    fn from_usize(index: usize) -> K {
+        unify::key_from_usize(index)
+    }
+
+    fn to_usize(self) -> usize {
+        self.index().as_usize()
+    }
+}
@@ -16,9 +16,20 @@
 #[macro_use]
 extern crate log;
 
-#[cfg(feature = "persistent")]
-extern crate dogged;
+#[cfg(feature = "congruence-closure")]
+extern crate petgraph;
 
+#[cfg(feature = "serde")]
+extern crate serde;
+
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+pub mod bitvec;
+#[cfg(feature = "congruence-closure")]
+pub mod cc;
+pub mod idx;
 pub mod snapshot_vec;
+pub mod transitive_relation;
 pub mod undo_log;
 pub mod unify;
@@ -18,21 +18,37 @@
 //! ensure that any changes you make this with this pointer are rolled back, you must invoke
 //! `record` to record any changes you make and also supplying a delegate capable of reversing
 //! those changes.
+//!
+//! `IndexedSnapshotVec` is a thin wrapper that is indexed by an `Idx` type, such as a
+//! `UnifyKey`, instead of `usize`.
 
 use self::UndoLog::*;
 
 use std::fmt;
 use std::marker::PhantomData;
 use std::mem;
-use std::ops;
+use std::ops::{self, Range};
+
+use idx::Idx;
+use undo_log::{Rollback, SnapshotError, Snapshots, UndoLogs, VecLog};
 
-use undo_log::{Rollback, Snapshots, UndoLogs, VecLog};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
+/// An entry in the undo log of a `SnapshotVec`.
+///
+/// More kinds of entry may be added, so code outside this crate that
+/// matches on an `UndoLog` needs a wildcard arm. Adding `NewElems` (and
+/// this attribute) is a breaking change, released in 0.15.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum UndoLog<D: SnapshotVecDelegate> {
     /// New variable with given index was created.
     NewElem(usize),
 
+    /// New variables with the given indices were created, as a batch.
+    NewElems(Range<usize>),
+
     /// Variable with given index was changed *from* the given value.
     SetElem(usize, D::Value),
 
@@ -53,6 +69,11 @@ impl<D: SnapshotVecDelegate> Rollback<UndoLog<D>> for Vec<D::Value> {
                 assert!(Vec::len(self) == i);
             }
 
+            NewElems(range) => {
+                assert!(Vec::len(self) == range.end);
+                self.truncate(range.start);
+            }
+
             SetElem(i, v) => {
                 self[i] = v;
             }
@@ -70,6 +91,9 @@ where
 {
     fn push(&mut self, item: D::Value);
     fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
     fn reserve(&mut self, size: usize);
 }
 
@@ -107,6 +131,18 @@ where
 pub type SnapshotVecStorage<D: SnapshotVecDelegate> =
     SnapshotVec<D, Vec<<D as SnapshotVecDelegate>::Value>, ()>;
 
+/// With the `serde` feature, a `SnapshotVec` serializes its values and
+/// its undo log; the default `VecLog` refuses to be serialized while a
+/// snapshot is open. A `SnapshotVecStorage` has no log of its own, so
+/// it is up to the caller not to serialize it in a snapshot.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "V: Serialize, L: Serialize",
+        deserialize = "V: Deserialize<'de>, L: Deserialize<'de>"
+    ))
+)]
 pub struct SnapshotVec<
     D: SnapshotVecDelegate,
     V: VecLike<D> = Vec<<D as SnapshotVecDelegate>::Value>,
@@ -114,6 +150,7 @@ pub struct SnapshotVec<
 > {
     values: V,
     undo_log: L,
+    #[cfg_attr(feature = "serde", serde(skip))]
     _marker: PhantomData<D>,
 }
 
@@ -139,6 +176,11 @@ pub struct Snapshot<S = ::undo_log::Snapshot> {
 
 pub trait SnapshotVecDelegate {
     type Value;
+
+    /// Undo actions beyond those of `UndoLog`, recorded with `record`
+    /// or `update_with_undo`. They can describe changes to a part of an
+    /// element, so that undoing them does not need a clone of the whole
+    /// element.
     type Undo;
 
     fn reverse(values: &mut Vec<Self::Value>, action: Self::Undo);
@@ -190,11 +232,26 @@ impl<D: SnapshotVecDelegate, L: Default> SnapshotVec<D, Vec<D::Value>, L> {
     }
 }
 
+/// Wraps existing values; no snapshot is open on the resulting vector.
+impl<D: SnapshotVecDelegate, L: Default> From<Vec<D::Value>> for SnapshotVec<D, Vec<D::Value>, L> {
+    fn from(values: Vec<D::Value>) -> Self {
+        SnapshotVec {
+            values,
+            undo_log: Default::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
 impl<V: VecLike<D>, D: SnapshotVecDelegate, U> SnapshotVec<D, V, U> {
     pub fn len(&self) -> usize {
         self.values.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.values.len() == 0
+    }
+
     pub fn get(&self, index: usize) -> &D::Value {
         &self.values.as_ref()[index]
     }
@@ -214,7 +271,8 @@ impl<V: VecLike<D>, D: SnapshotVecDelegate, U> SnapshotVec<D, V, U> {
 }
 
 impl<V: VecLike<D>, D: SnapshotVecDelegate, L: UndoLogs<UndoLog<D>>> SnapshotVec<D, V, L> {
-    fn in_snapshot(&self) -> bool {
+    /// True if a snapshot is open, i.e. changes are being recorded in the undo log.
+    pub fn in_snapshot(&self) -> bool {
         self.undo_log.in_snapshot()
     }
 
@@ -235,6 +293,23 @@ impl<V: VecLike<D>, D: SnapshotVecDelegate, L: UndoLogs<UndoLog<D>>> SnapshotVec
         len
     }
 
+    /// Pushes every element of `elems` and returns their indices. Only
+    /// one entry is added to the undo log, however many elements there
+    /// are.
+    pub fn push_all(&mut self, elems: impl IntoIterator<Item = D::Value>) -> Range<usize> {
+        let start = self.values.len();
+        for elem in elems {
+            self.values.push(elem);
+        }
+        let end = self.values.len();
+
+        if self.in_snapshot() && start != end {
+            self.undo_log.push(NewElems(start..end));
+        }
+
+        start..end
+    }
+
     /// Updates the element at the given index. The old value will saved (and perhaps restored) if
     /// a snapshot is active.
     pub fn set(&mut self, index: usize, new_elem: D::Value) {
@@ -269,6 +344,20 @@ impl<V: VecLike<D>, D: SnapshotVecDelegate, L: UndoLogs<UndoLog<D>>> SnapshotVec
         }
         op(&mut self.values.as_mut()[index]);
     }
+
+    /// Updates the element at the given index with `op`, which returns
+    /// an action that reverses its changes. The action is recorded if a
+    /// snapshot is active, and passed to `SnapshotVecDelegate::reverse`
+    /// on rollback. Unlike `update`, this never clones the element.
+    pub fn update_with_undo<OP>(&mut self, index: usize, op: OP)
+    where
+        OP: FnOnce(&mut D::Value) -> D::Undo,
+    {
+        let action = op(&mut self.values.as_mut()[index]);
+        if self.undo_log.in_snapshot() {
+            self.undo_log.push(Other(action));
+        }
+    }
 }
 
 impl<D, V, L> SnapshotVec<D, V, L>
@@ -298,6 +387,180 @@ where
     pub fn commit(&mut self, snapshot: Snapshot<L::Snapshot>) {
         self.undo_log.commit(snapshot.snapshot);
     }
+
+    /// Checks that `snapshot` is the innermost open snapshot, as far as
+    /// the undo log can tell, without consuming it.
+    pub fn check_snapshot(&self, snapshot: &Snapshot<L::Snapshot>) -> Result<(), SnapshotError> {
+        self.undo_log.check_snapshot(&snapshot.snapshot)
+    }
+
+    /// Like `rollback_to`, but returns an error if `snapshot` is not the
+    /// innermost open snapshot, as far as the undo log can tell. The
+    /// error hands the snapshot back.
+    pub fn try_rollback_to(
+        &mut self,
+        snapshot: Snapshot<L::Snapshot>,
+    ) -> Result<(), (Snapshot<L::Snapshot>, SnapshotError)> {
+        let values = &mut self.values;
+        let value_count = snapshot.value_count;
+        self.undo_log
+            .try_rollback_to(|| values, snapshot.snapshot)
+            .map_err(|(snapshot, err)| {
+                (
+                    Snapshot {
+                        value_count,
+                        snapshot,
+                    },
+                    err,
+                )
+            })
+    }
+
+    /// Like `commit`, but returns an error if `snapshot` is not the
+    /// innermost open snapshot, as far as the undo log can tell. The
+    /// error hands the snapshot back.
+    pub fn try_commit(
+        &mut self,
+        snapshot: Snapshot<L::Snapshot>,
+    ) -> Result<(), (Snapshot<L::Snapshot>, SnapshotError)> {
+        let value_count = snapshot.value_count;
+        self.undo_log
+            .try_commit(snapshot.snapshot)
+            .map_err(|(snapshot, err)| {
+                (
+                    Snapshot {
+                        value_count,
+                        snapshot,
+                    },
+                    err,
+                )
+            })
+    }
+
+    /// Runs `f` inside a snapshot that is always rolled back; see
+    /// `UnificationTable::probe`.
+    pub fn probe<R, F>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let snapshot = self.start_snapshot();
+        let result = f(self);
+        self.rollback_to(snapshot);
+        result
+    }
+
+    /// Runs `f` inside a snapshot, which is committed if `f` returns
+    /// `Ok` and rolled back if it returns `Err`.
+    pub fn commit_if_ok<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Self) -> Result<T, E>,
+    {
+        let snapshot = self.start_snapshot();
+        let result = f(self);
+        if result.is_ok() {
+            self.commit(snapshot);
+        } else {
+            self.rollback_to(snapshot);
+        }
+        result
+    }
+
+    /// Runs `f` inside a snapshot that is always committed.
+    pub fn commit_unconditionally<R, F>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let snapshot = self.start_snapshot();
+        let result = f(self);
+        self.commit(snapshot);
+        result
+    }
+
+    /// Starts a new snapshot and returns a guard that derefs to the
+    /// vector and rolls the snapshot back when dropped, unless
+    /// `SnapshotGuard::commit` is called first.
+    pub fn snapshot_guard(&mut self) -> SnapshotGuard<'_, D, V, L> {
+        let snapshot = self.start_snapshot();
+        SnapshotGuard {
+            vec: self,
+            snapshot: Some(snapshot),
+        }
+    }
+}
+
+/// A snapshot of a `SnapshotVec` that is rolled back when dropped. See
+/// `SnapshotVec::snapshot_guard`.
+pub struct SnapshotGuard<'a, D, V, L>
+where
+    D: SnapshotVecDelegate,
+    V: VecLike<D>,
+    L: Snapshots<UndoLog<D>>,
+{
+    vec: &'a mut SnapshotVec<D, V, L>,
+    // Only `None` once the snapshot has been committed or rolled back.
+    snapshot: Option<Snapshot<L::Snapshot>>,
+}
+
+impl<'a, D, V, L> SnapshotGuard<'a, D, V, L>
+where
+    D: SnapshotVecDelegate,
+    V: VecLike<D>,
+    L: Snapshots<UndoLog<D>>,
+{
+    /// Commits all changes made since the guard was created.
+    pub fn commit(mut self) {
+        let snapshot = self.snapshot.take().unwrap();
+        self.vec.commit(snapshot);
+    }
+
+    /// Rolls back all changes made since the guard was created, just
+    /// like dropping the guard.
+    pub fn rollback(mut self) {
+        let snapshot = self.snapshot.take().unwrap();
+        self.vec.rollback_to(snapshot);
+    }
+
+    /// Returns the actions taken since the guard was created.
+    pub fn actions_since_snapshot(&self) -> &[UndoLog<D>] {
+        self.vec
+            .actions_since_snapshot(self.snapshot.as_ref().unwrap())
+    }
+}
+
+impl<'a, D, V, L> ops::Deref for SnapshotGuard<'a, D, V, L>
+where
+    D: SnapshotVecDelegate,
+    V: VecLike<D>,
+    L: Snapshots<UndoLog<D>>,
+{
+    type Target = SnapshotVec<D, V, L>;
+    fn deref(&self) -> &SnapshotVec<D, V, L> {
+        self.vec
+    }
+}
+
+impl<'a, D, V, L> ops::DerefMut for SnapshotGuard<'a, D, V, L>
+where
+    D: SnapshotVecDelegate,
+    V: VecLike<D>,
+    L: Snapshots<UndoLog<D>>,
+{
+    fn deref_mut(&mut self) -> &mut SnapshotVec<D, V, L> {
+        self.vec
+    }
+}
+
+impl<'a, D, V, L> Drop for SnapshotGuard<'a, D, V, L>
+where
+    D: SnapshotVecDelegate,
+    V: VecLike<D>,
+    L: Snapshots<UndoLog<D>>,
+{
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            self.vec.rollback_to(snapshot);
+        }
+    }
 }
 
 impl<D: SnapshotVecDelegate, V: VecLike<D>, L> ops::Deref for SnapshotVec<D, V, L> {
@@ -337,9 +600,8 @@ impl<D: SnapshotVecDelegate, V: VecLike<D> + Extend<D::Value>> Extend<D::Value>
         self.values.extend(iterable);
         let final_len = self.values.len();
 
-        if self.in_snapshot() {
-            self.undo_log
-                .extend((initial_len..final_len).map(|len| NewElem(len)));
+        if self.in_snapshot() && initial_len != final_len {
+            self.undo_log.push(NewElems(initial_len..final_len));
         }
     }
 }
@@ -366,12 +628,221 @@ where
     fn clone(&self) -> Self {
         match *self {
             NewElem(i) => NewElem(i),
+            NewElems(ref range) => NewElems(range.clone()),
             SetElem(i, ref v) => SetElem(i, v.clone()),
             Other(ref u) => Other(u.clone()),
         }
     }
 }
 
+/// A `SnapshotVec` indexed by `I` rather than by `usize`, so that keys
+/// meant for one table cannot be used with another by mistake. `push`
+/// returns the index of the new element as an `I`.
+pub struct IndexedSnapshotVec<
+    I: Idx,
+    D: SnapshotVecDelegate,
+    V: VecLike<D> = Vec<<D as SnapshotVecDelegate>::Value>,
+    L = VecLog<UndoLog<D>>,
+> {
+    vec: SnapshotVec<D, V, L>,
+    marker: PhantomData<fn(I) -> I>,
+}
+
+#[allow(type_alias_bounds)]
+pub type IndexedSnapshotVecStorage<I: Idx, D: SnapshotVecDelegate> =
+    IndexedSnapshotVec<I, D, Vec<<D as SnapshotVecDelegate>::Value>, ()>;
+
+impl<I, D, V, L> fmt::Debug for IndexedSnapshotVec<I, D, V, L>
+where
+    I: Idx,
+    D: SnapshotVecDelegate,
+    V: VecLike<D> + fmt::Debug,
+    L: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("IndexedSnapshotVec")
+            .field("vec", &self.vec)
+            .finish()
+    }
+}
+
+// HACK(eddyb) manual impl avoids `Default` bound on `I` and `D`.
+impl<I: Idx, D: SnapshotVecDelegate, V: VecLike<D> + Default, L: Default> Default
+    for IndexedSnapshotVec<I, D, V, L>
+{
+    fn default() -> Self {
+        IndexedSnapshotVec {
+            vec: SnapshotVec::default(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<I: Idx, D: SnapshotVecDelegate, V: VecLike<D> + Default, L: Default>
+    IndexedSnapshotVec<I, D, V, L>
+{
+    /// Creates a new `IndexedSnapshotVec`; see `SnapshotVec::new`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<I: Idx, D: SnapshotVecDelegate> IndexedSnapshotVecStorage<I, D> {
+    /// Creates an `IndexedSnapshotVec` using the `undo_log`, allowing mutating methods to be
+    /// called
+    pub fn with_log<L>(
+        &mut self,
+        undo_log: L,
+    ) -> IndexedSnapshotVec<I, D, &mut Vec<<D as SnapshotVecDelegate>::Value>, L>
+    where
+        L: UndoLogs<UndoLog<D>>,
+    {
+        IndexedSnapshotVec {
+            vec: self.vec.with_log(undo_log),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<I: Idx, D: SnapshotVecDelegate> Rollback<UndoLog<D>> for IndexedSnapshotVecStorage<I, D> {
+    fn reverse(&mut self, undo: UndoLog<D>) {
+        self.vec.reverse(undo)
+    }
+}
+
+impl<I: Idx, D: SnapshotVecDelegate, V: VecLike<D>, L> IndexedSnapshotVec<I, D, V, L> {
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
+    pub fn get(&self, index: I) -> &D::Value {
+        self.vec.get(index.to_usize())
+    }
+
+    /// Returns a mutable pointer into the vec; see `SnapshotVec::get_mut`.
+    pub fn get_mut(&mut self, index: I) -> &mut D::Value {
+        self.vec.get_mut(index.to_usize())
+    }
+
+    /// Reserve space for new values, just like an ordinary vec.
+    pub fn reserve(&mut self, additional: usize) {
+        self.vec.reserve(additional);
+    }
+
+    /// Iterates over the indices of all elements, in order.
+    pub fn indices(&self) -> impl Iterator<Item = I> {
+        (0..self.vec.len()).map(I::from_usize)
+    }
+}
+
+impl<I, D, V, L> IndexedSnapshotVec<I, D, V, L>
+where
+    I: Idx,
+    D: SnapshotVecDelegate,
+    V: VecLike<D>,
+    L: UndoLogs<UndoLog<D>>,
+{
+    /// True if a snapshot is open, i.e. changes are being recorded in the undo log.
+    pub fn in_snapshot(&self) -> bool {
+        self.vec.in_snapshot()
+    }
+
+    pub fn record(&mut self, action: D::Undo) {
+        self.vec.record(action);
+    }
+
+    pub fn push(&mut self, elem: D::Value) -> I {
+        I::from_usize(self.vec.push(elem))
+    }
+
+    /// Updates the element at the given index. The old value will saved (and perhaps restored) if
+    /// a snapshot is active.
+    pub fn set(&mut self, index: I, new_elem: D::Value) {
+        self.vec.set(index.to_usize(), new_elem);
+    }
+
+    /// Updates all elements; see `SnapshotVec::set_all`.
+    pub fn set_all(&mut self, mut new_elems: impl FnMut(I) -> D::Value) {
+        self.vec.set_all(|index| new_elems(I::from_usize(index)));
+    }
+
+    pub fn update<OP>(&mut self, index: I, op: OP)
+    where
+        OP: FnOnce(&mut D::Value),
+        D::Value: Clone,
+    {
+        self.vec.update(index.to_usize(), op);
+    }
+
+    /// See `SnapshotVec::update_with_undo`.
+    pub fn update_with_undo<OP>(&mut self, index: I, op: OP)
+    where
+        OP: FnOnce(&mut D::Value) -> D::Undo,
+    {
+        self.vec.update_with_undo(index.to_usize(), op);
+    }
+}
+
+impl<I, D, V, L> IndexedSnapshotVec<I, D, V, L>
+where
+    I: Idx,
+    D: SnapshotVecDelegate,
+    V: VecLike<D> + Rollback<UndoLog<D>>,
+    L: Snapshots<UndoLog<D>>,
+{
+    pub fn start_snapshot(&mut self) -> Snapshot<L::Snapshot> {
+        self.vec.start_snapshot()
+    }
+
+    pub fn actions_since_snapshot(&self, snapshot: &Snapshot<L::Snapshot>) -> &[UndoLog<D>] {
+        self.vec.actions_since_snapshot(snapshot)
+    }
+
+    pub fn rollback_to(&mut self, snapshot: Snapshot<L::Snapshot>) {
+        self.vec.rollback_to(snapshot);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: Snapshot<L::Snapshot>) {
+        self.vec.commit(snapshot);
+    }
+}
+
+impl<I: Idx, D: SnapshotVecDelegate, V: VecLike<D>, L> ops::Index<I>
+    for IndexedSnapshotVec<I, D, V, L>
+{
+    type Output = D::Value;
+    fn index(&self, index: I) -> &D::Value {
+        self.get(index)
+    }
+}
+
+impl<I: Idx, D: SnapshotVecDelegate, V: VecLike<D>, L> ops::IndexMut<I>
+    for IndexedSnapshotVec<I, D, V, L>
+{
+    fn index_mut(&mut self, index: I) -> &mut D::Value {
+        self.get_mut(index)
+    }
+}
+
+impl<I: Idx, D: SnapshotVecDelegate, V, L> Clone for IndexedSnapshotVec<I, D, V, L>
+where
+    V: VecLike<D> + Clone,
+    L: Clone,
+{
+    fn clone(&self) -> Self {
+        IndexedSnapshotVec {
+            vec: self.vec.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
 impl SnapshotVecDelegate for i32 {
     type Value = i32;
     type Undo = ();
@@ -423,8 +894,8 @@ fn out_of_order() {
     vec.push(33);
     let snapshot2 = vec.start_snapshot();
     vec.push(44);
-    vec.rollback_to(snapshot1); // bogus, but accepted
-    vec.rollback_to(snapshot2); // asserts
+    vec.rollback_to(snapshot1); // asserts, `snapshot2` is still open
+    vec.rollback_to(snapshot2);
 }
 
 #[test]
@@ -439,3 +910,211 @@ fn nested_commit_then_rollback() {
     vec.rollback_to(snapshot1);
     assert_eq!(*vec.get(0), 22);
 }
+
+// Counts and names; only the counts are changed by `update_with_undo`.
+#[cfg(test)]
+#[derive(Debug)]
+struct Counted;
+
+#[cfg(test)]
+impl SnapshotVecDelegate for Counted {
+    type Value = (u32, String);
+    type Undo = (usize, u32);
+
+    fn reverse(values: &mut Vec<(u32, String)>, (index, count): (usize, u32)) {
+        values[index].0 = count;
+    }
+}
+
+#[test]
+fn update_with_undo() {
+    let mut vec: SnapshotVec<Counted> = SnapshotVec::default();
+    vec.push((0, "a".to_string()));
+    vec.update_with_undo(0, |elem| {
+        elem.0 += 1;
+        (0, elem.0 - 1)
+    });
+    assert_eq!(vec.get(0).0, 1);
+
+    let snapshot = vec.start_snapshot();
+    for _ in 0..2 {
+        vec.update_with_undo(0, |elem| {
+            elem.0 += 1;
+            (0, elem.0 - 1)
+        });
+    }
+    assert_eq!(vec.get(0).0, 3);
+    match vec.actions_since_snapshot(&snapshot) {
+        [Other((0, 1)), Other((0, 2))] => {}
+        actions => panic!("unexpected undo log: {:?}", actions),
+    }
+    vec.rollback_to(snapshot);
+    assert_eq!(*vec.get(0), (1, "a".to_string()));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    vec.push(33);
+
+    let snapshot = vec.start_snapshot();
+    vec.set(0, 23);
+    assert!(serde_json::to_string(&vec).is_err());
+    vec.commit(snapshot);
+
+    let json = serde_json::to_string(&vec).unwrap();
+    let mut vec: SnapshotVec<i32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(vec.len(), 2);
+    assert_eq!(*vec.get(0), 23);
+    assert_eq!(*vec.get(1), 33);
+
+    let snapshot = vec.start_snapshot();
+    vec.push(44);
+    vec.rollback_to(snapshot);
+    assert_eq!(vec.len(), 2);
+}
+
+#[cfg(test)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct TestKey(u32);
+
+#[cfg(test)]
+impl ::unify::UnifyKey for TestKey {
+    type Value = ();
+    type Index = u32;
+    fn index(&self) -> u32 {
+        self.0
+    }
+    fn from_index(u: u32) -> TestKey {
+        TestKey(u)
+    }
+    fn tag() -> &'static str {
+        "TestKey"
+    }
+}
+
+#[test]
+fn indexed() {
+    let mut vec: IndexedSnapshotVec<TestKey, i32> = IndexedSnapshotVec::new();
+    let a = vec.push(22);
+    let b = vec.push(33);
+    assert_eq!(b, TestKey(1));
+    assert_eq!(vec[a], 22);
+
+    let snapshot = vec.start_snapshot();
+    vec.set(b, 34);
+    vec.update(a, |value| *value += 1);
+    let c = vec.push(44);
+    assert_eq!(vec.indices().collect::<Vec<_>>(), [a, b, c]);
+    vec.rollback_to(snapshot);
+
+    assert_eq!(vec.len(), 2);
+    assert_eq!(vec[a], 22);
+    assert_eq!(*vec.get(b), 33);
+    vec[b] = 35;
+    assert_eq!(vec[b], 35);
+}
+
+#[test]
+fn snapshot_combinators() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    let len = vec.probe(|vec| {
+        vec.set(0, 23);
+        vec.push(33);
+        vec.len()
+    });
+    assert_eq!(len, 2);
+    assert_eq!(vec.len(), 1);
+    assert_eq!(*vec.get(0), 22);
+
+    let result: Result<(), ()> = vec.commit_if_ok(|vec| {
+        vec.push(33);
+        Err(())
+    });
+    assert!(result.is_err());
+    assert_eq!(vec.len(), 1);
+
+    let result: Result<usize, ()> = vec.commit_if_ok(|vec| Ok(vec.push(33)));
+    assert_eq!(result, Ok(1));
+    assert_eq!(vec.len(), 2);
+
+    let snapshot = vec.start_snapshot();
+    vec.commit_unconditionally(|vec| vec.set(1, 34));
+    assert_eq!(*vec.get(1), 34);
+    vec.rollback_to(snapshot);
+    assert_eq!(*vec.get(1), 33);
+}
+
+#[test]
+fn snapshot_guard() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    {
+        let mut guard = vec.snapshot_guard();
+        guard.push(33);
+        guard.set(0, 23);
+        assert_eq!(guard.actions_since_snapshot().len(), 2);
+    }
+    assert_eq!(vec.len(), 1);
+    assert_eq!(*vec.get(0), 22);
+    assert!(!vec.in_snapshot());
+
+    let mut guard = vec.snapshot_guard();
+    guard.push(33);
+    guard.commit();
+    assert_eq!(vec.len(), 2);
+    assert!(!vec.in_snapshot());
+}
+
+#[test]
+fn try_out_of_order() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    let snapshot1 = vec.start_snapshot();
+    vec.push(33);
+    let snapshot2 = vec.start_snapshot();
+    vec.push(44);
+    let (snapshot1, err) = vec.try_rollback_to(snapshot1).unwrap_err();
+    assert_eq!(
+        err,
+        SnapshotError::OutOfOrder {
+            depth: 1,
+            innermost: 2
+        }
+    );
+    assert_eq!(vec.len(), 3);
+    assert!(vec.try_rollback_to(snapshot2).is_ok());
+    assert_eq!(vec.len(), 2);
+
+    // The error handed the snapshot back, so it can still be closed.
+    assert!(vec.try_rollback_to(snapshot1).is_ok());
+    assert_eq!(vec.len(), 1);
+    assert!(!vec.in_snapshot());
+
+    let mut other: SnapshotVec<i32> = SnapshotVec::default();
+    let foreign = other.start_snapshot();
+    assert_eq!(
+        vec.try_commit(foreign).unwrap_err().1,
+        SnapshotError::Foreign
+    );
+}
+
+#[test]
+fn push_all() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    assert_eq!(vec.push_all(vec![22, 33]), 0..2);
+
+    let snapshot = vec.start_snapshot();
+    assert_eq!(vec.push_all(vec![44, 55, 66]), 2..5);
+    assert_eq!(vec.push_all(vec![]), 5..5);
+    vec.extend(vec![77, 88]);
+    assert_eq!(vec.len(), 7);
+    assert_eq!(vec.actions_since_snapshot(&snapshot).len(), 2);
+
+    vec.rollback_to(snapshot);
+    assert_eq!(vec.len(), 2);
+    assert_eq!(*vec.get(1), 33);
+}
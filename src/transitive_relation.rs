@@ -0,0 +1,523 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A relation over arbitrary elements, together with its transitive
+//! closure. This is the kind of structure that rustc's region code
+//! uses to model "outlives" relationships between free regions.
+//!
+//! Edges are added one at a time with `add`; the closure is computed
+//! lazily, the first time it is needed after a change, and stored in a
+//! `BitMatrix` with one row per element.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::mem;
+
+use bitvec::BitMatrix;
+
+#[derive(Clone, Debug)]
+pub struct TransitiveRelation<T: Clone + Debug + Eq + Hash> {
+    // List of elements. This is used to map from a T to a usize.
+    elements: Vec<T>,
+
+    // Maps each element to an index.
+    map: HashMap<T, usize>,
+
+    // List of base edges in the graph. Required to compute the
+    // transitive closure.
+    edges: Vec<Edge>,
+
+    // This is a cached transitive closure derived from the edges.
+    // Currently, we build it lazily and just throw out any existing
+    // copy whenever a new edge or element is added.
+    closure: RefCell<Option<BitMatrix>>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Edge {
+    source: usize,
+    target: usize,
+}
+
+impl<T: Clone + Debug + Eq + Hash> Default for TransitiveRelation<T> {
+    fn default() -> Self {
+        TransitiveRelation {
+            elements: vec![],
+            map: HashMap::new(),
+            edges: vec![],
+            closure: RefCell::new(None),
+        }
+    }
+}
+
+impl<T: Clone + Debug + Eq + Hash> TransitiveRelation<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if no edges have been added.
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    fn index(&self, a: &T) -> Option<usize> {
+        self.map.get(a).cloned()
+    }
+
+    fn add_index(&mut self, a: T) -> usize {
+        if let Some(&index) = self.map.get(&a) {
+            return index;
+        }
+        let index = self.elements.len();
+        self.elements.push(a.clone());
+        self.map.insert(a, index);
+
+        // If we changed the dimensions, clear the cache.
+        *self.closure.get_mut() = None;
+        index
+    }
+
+    /// Indicate that `a < b` (where `<` is this relation)
+    pub fn add(&mut self, a: T, b: T) {
+        let source = self.add_index(a);
+        let target = self.add_index(b);
+        let edge = Edge { source, target };
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+
+            // added an edge, clear the cache
+            *self.closure.get_mut() = None;
+        }
+    }
+
+    /// Check whether `a < b` (transitively). Note that an element
+    /// only reaches itself if it is on a cycle.
+    pub fn reachable(&self, a: &T, b: &T) -> bool {
+        match (self.index(a), self.index(b)) {
+            (Some(a), Some(b)) => self.with_closure(|closure| closure.contains(a, b)),
+            (None, _) | (_, None) => false,
+        }
+    }
+
+    /// Picks what I am referring to as the "postdominating"
+    /// upper-bound for `a` and `b`. This is usually the least upper
+    /// bound, but in cases where there is no single least upper
+    /// bound, it is the "mutual immediate postdominator", if you
+    /// imagine a graph where `a < b` means `a -> b`.
+    ///
+    /// This function is needed because region inference currently
+    /// requires that we produce a single "UB", and there is no best
+    /// choice for the LUB. Rather than pick arbitrarily, I pick a
+    /// less good, but predictable choice. This should help ensure
+    /// that region inference yields predictable results (though it
+    /// itself is not fully sufficient).
+    ///
+    /// Examples are probably clearer than any prose I could write
+    /// (there are corresponding tests below, btw). In each case,
+    /// the query is `postdom_upper_bound(a, b)`:
+    ///
+    /// ```text
+    /// // returns Some(x), which is also LUB
+    /// a -> a1 -> x
+    ///            ^
+    ///            |
+    /// b -> b1 ---+
+    ///
+    /// // returns Some(x), which is not LUB (there is none)
+    /// // diagonal edges run left-to-right
+    /// a -> a1 -> x
+    ///   \/       ^
+    ///   /\       |
+    /// b -> b1 ---+
+    ///
+    /// // returns None
+    /// a -> a1
+    /// b -> b1
+    /// ```
+    pub fn postdom_upper_bound(&self, a: &T, b: &T) -> Option<&T> {
+        let mubs = self.minimal_upper_bounds(a, b);
+        self.mutual_immediate_postdominator(mubs)
+    }
+
+    /// Viewing the relation as a graph, computes the "mutual
+    /// immediate postdominator" of a set of points (if one
+    /// exists). See `postdom_upper_bound` for details.
+    pub fn mutual_immediate_postdominator<'a>(&'a self, mut mubs: Vec<&'a T>) -> Option<&'a T> {
+        loop {
+            match mubs.len() {
+                0 => return None,
+                1 => return Some(mubs[0]),
+                _ => {
+                    let m = mubs.pop().unwrap();
+                    let n = mubs.pop().unwrap();
+                    mubs.extend(self.minimal_upper_bounds(n, m));
+                }
+            }
+        }
+    }
+
+    /// Returns the set of bounds `X` such that:
+    ///
+    /// - `a < X` and `b < X`
+    /// - there is no `Y != X` such that `a < Y` and `Y < X`
+    ///   - except for the case where `X < a` (i.e., a strongly connected
+    ///     component in the graph). In that case, the smallest
+    ///     representative of the SCC is returned (as determined by the
+    ///     internal indices).
+    ///
+    /// Note that this set can, in principle, have any size.
+    pub fn minimal_upper_bounds(&self, a: &T, b: &T) -> Vec<&T> {
+        let (mut a, mut b) = match (self.index(a), self.index(b)) {
+            (Some(a), Some(b)) => (a, b),
+            (None, _) | (_, None) => return vec![],
+        };
+
+        // in some cases, there are some arbitrary choices to be made;
+        // it doesn't really matter what we pick, as long as we pick
+        // the same thing consistently when queried, so ensure that
+        // (a, b) are in a consistent relative order
+        if a > b {
+            mem::swap(&mut a, &mut b);
+        }
+
+        let lub_indices = self.with_closure(|closure| {
+            // Easy case is when either a < b or b < a:
+            if closure.contains(a, b) {
+                return vec![b];
+            }
+            if closure.contains(b, a) {
+                return vec![a];
+            }
+
+            // Otherwise, the tricky part is that there may be some c
+            // where a < c and b < c. In fact, there may be many such
+            // values. So here is what we do:
+            //
+            // 1. Find the vector `[X | a < X && b < X]` of all values
+            //    `X` where `a < X` and `b < X`. In terms of the graph,
+            //    this means all nodes reachable from both `a` and `b`.
+            //    These are upper bounds, but not necessarily minimal
+            //    ones: if `x < y`, both may be in the vector.
+            // 2. Pare down the vector using `pare_down`. This removes
+            //    every element that can be reached by an *earlier*
+            //    element of the vector.
+            // 3. Reverse the vector and pare down again, which removes
+            //    the elements that could be reached by a *later*
+            //    element.
+            // 4. Reverse once more, so that we yield a vector in
+            //    increasing order of index. Not necessary, but why not.
+            //
+            // After step 2, no element can reach its successors in the
+            // vector; after step 3, no element can reach its
+            // predecessors either, so what is left is minimal.
+            let mut candidates = closure.intersection(a, b); // (1)
+            pare_down(&mut candidates, closure); // (2)
+            candidates.reverse(); // (3a)
+            pare_down(&mut candidates, closure); // (3b)
+            candidates
+        });
+
+        lub_indices
+            .into_iter()
+            .rev() // (4)
+            .map(|i| &self.elements[i])
+            .collect()
+    }
+
+    fn with_closure<OP, R>(&self, op: OP) -> R
+    where
+        OP: FnOnce(&BitMatrix) -> R,
+    {
+        let mut closure_cell = self.closure.borrow_mut();
+        let mut closure = closure_cell.take();
+        if closure.is_none() {
+            closure = Some(self.compute_closure());
+        }
+        let result = op(closure.as_ref().unwrap());
+        *closure_cell = closure;
+        result
+    }
+
+    /// Computes the closure with Warshall's algorithm, one row at a
+    /// time: once row `k` is complete for paths through `0..k`, every
+    /// row that reaches `k` absorbs it.
+    fn compute_closure(&self) -> BitMatrix {
+        let n = self.elements.len();
+        let mut matrix = BitMatrix::new(n);
+        for edge in &self.edges {
+            matrix.add(edge.source, edge.target);
+        }
+        for k in 0..n {
+            for i in 0..n {
+                if matrix.contains(i, k) {
+                    matrix.merge(k, i);
+                }
+            }
+        }
+        matrix
+    }
+}
+
+/// Pare down is used as a step in the LUB computation. It edits the
+/// candidates array in place by removing any element j for which
+/// there exists an earlier element i<j such that i -> j. That is,
+/// after you run `pare_down`, you know that for all elements that
+/// remain in candidates, they cannot reach any of the elements that
+/// come after them.
+///
+/// Examples follow. Assume that a -> b -> c and x -> y -> z.
+///
+/// - Input: `[a, b, x]`. Output: `[a, x]`.
+/// - Input: `[b, a, x]`. Output: `[b, a, x]`.
+/// - Input: `[a, x, b, y]`. Output: `[a, x]`.
+fn pare_down(candidates: &mut Vec<usize>, closure: &BitMatrix) {
+    let mut i = 0;
+    while i < candidates.len() {
+        let candidate_i = candidates[i];
+        i += 1;
+
+        let mut j = i;
+        let mut dead = 0;
+        while j < candidates.len() {
+            let candidate_j = candidates[j];
+            if closure.contains(candidate_i, candidate_j) {
+                // If `i` can reach `j`, then we can remove `j`. So just
+                // mark it as dead and move on; subsequent indices will be
+                // shifted into its place.
+                dead += 1;
+            } else {
+                candidates[j - dead] = candidate_j;
+            }
+            j += 1;
+        }
+        candidates.truncate(j - dead);
+    }
+}
+
+#[test]
+fn test_one_step() {
+    let mut relation = TransitiveRelation::new();
+    relation.add("a", "b");
+    relation.add("a", "c");
+    assert!(relation.reachable(&"a", &"c"));
+    assert!(relation.reachable(&"a", &"b"));
+    assert!(!relation.reachable(&"b", &"a"));
+    assert!(!relation.reachable(&"a", &"d"));
+}
+
+#[test]
+fn test_many_steps() {
+    let mut relation = TransitiveRelation::new();
+    relation.add("a", "b");
+    relation.add("a", "c");
+    relation.add("a", "f");
+
+    relation.add("b", "c");
+    relation.add("b", "d");
+    relation.add("b", "e");
+
+    relation.add("e", "g");
+
+    assert!(relation.reachable(&"a", &"b"));
+    assert!(relation.reachable(&"a", &"g"));
+    assert!(relation.reachable(&"b", &"g"));
+    assert!(!relation.reachable(&"a", &"a"));
+    assert!(!relation.reachable(&"g", &"b"));
+}
+
+#[test]
+fn test_cycle() {
+    let mut relation = TransitiveRelation::new();
+    relation.add("a", "b");
+    relation.add("b", "c");
+    assert!(!relation.reachable(&"a", &"a"));
+
+    // Adding an edge after a query invalidates the cached closure.
+    relation.add("c", "a");
+    assert!(relation.reachable(&"a", &"a"));
+    assert!(relation.reachable(&"c", &"b"));
+}
+
+#[test]
+fn mubs_triangle() {
+    // a -> tcx
+    //      ^
+    //      |
+    //      b
+    let mut relation = TransitiveRelation::new();
+    relation.add("a", "tcx");
+    relation.add("b", "tcx");
+    assert_eq!(relation.minimal_upper_bounds(&"a", &"b"), vec![&"tcx"]);
+}
+
+#[test]
+fn mubs_best_choice1() {
+    // 0 -> 1 <- 3
+    // |    ^    |
+    // |    |    |
+    // +--> 2 <--+
+    //
+    // mubs(0,3) = [1]
+    //
+    // This tests a particular state in the algorithm, in which we
+    // need the second pare down call to get the right result (after
+    // intersection, we have [1, 2], but 2 -> 1).
+    let mut relation = TransitiveRelation::new();
+    relation.add("0", "1");
+    relation.add("0", "2");
+
+    relation.add("2", "1");
+
+    relation.add("3", "1");
+    relation.add("3", "2");
+
+    assert_eq!(relation.minimal_upper_bounds(&"0", &"3"), vec![&"2"]);
+}
+
+#[test]
+fn mubs_best_choice2() {
+    // 0 -> 1 <- 3
+    // |    |    |
+    // |    v    |
+    // +--> 2 <--+
+    //
+    // mubs(0,3) = [2]
+    //
+    // Like the precedecing test, but in this case intersection is
+    // [2, 1], and hence we rely on the first pare down call.
+    let mut relation = TransitiveRelation::new();
+    relation.add("0", "1");
+    relation.add("0", "2");
+
+    relation.add("1", "2");
+
+    relation.add("3", "1");
+    relation.add("3", "2");
+
+    assert_eq!(relation.minimal_upper_bounds(&"0", &"3"), vec![&"1"]);
+}
+
+#[test]
+fn mubs_no_best_choice() {
+    // in this case, the intersection yields [1, 2], and the "pare
+    // down" calls find nothing to remove.
+    let mut relation = TransitiveRelation::new();
+    relation.add("0", "1");
+    relation.add("0", "2");
+
+    relation.add("3", "1");
+    relation.add("3", "2");
+
+    assert_eq!(relation.minimal_upper_bounds(&"0", &"3"), vec![&"1", &"2"]);
+}
+
+#[test]
+fn mubs_best_choice_scc() {
+    // in this case, 1 and 2 form a cycle; we pick arbitrarily (but
+    // consistently).
+    let mut relation = TransitiveRelation::new();
+    relation.add("0", "1");
+    relation.add("0", "2");
+
+    relation.add("1", "2");
+    relation.add("2", "1");
+
+    relation.add("3", "1");
+    relation.add("3", "2");
+
+    assert_eq!(relation.minimal_upper_bounds(&"0", &"3"), vec![&"1"]);
+}
+
+#[test]
+fn pdub_crisscross() {
+    // diagonal edges run left-to-right
+    // a -> a1 -> x
+    //   \/       ^
+    //   /\       |
+    // b -> b1 ---+
+    let mut relation = TransitiveRelation::new();
+    relation.add("a", "a1");
+    relation.add("a", "b1");
+    relation.add("b", "a1");
+    relation.add("b", "b1");
+    relation.add("a1", "x");
+    relation.add("b1", "x");
+
+    assert_eq!(
+        relation.minimal_upper_bounds(&"a", &"b"),
+        vec![&"a1", &"b1"]
+    );
+    assert_eq!(relation.postdom_upper_bound(&"a", &"b"), Some(&"x"));
+}
+
+#[test]
+fn pdub_crisscross_more() {
+    // diagonal edges run left-to-right
+    // a -> a1 -> a2 -> a3 -> x
+    //   \/    \/             ^
+    //   /\    /\             |
+    // b -> b1 -> b2 ---------+
+    let mut relation = TransitiveRelation::new();
+    relation.add("a", "a1");
+    relation.add("a", "b1");
+    relation.add("b", "a1");
+    relation.add("b", "b1");
+
+    relation.add("a1", "a2");
+    relation.add("a1", "b2");
+    relation.add("b1", "a2");
+    relation.add("b1", "b2");
+
+    relation.add("a2", "a3");
+
+    relation.add("a3", "x");
+    relation.add("b2", "x");
+
+    assert_eq!(
+        relation.minimal_upper_bounds(&"a", &"b"),
+        vec![&"a1", &"b1"]
+    );
+    assert_eq!(
+        relation.minimal_upper_bounds(&"a1", &"b1"),
+        vec![&"a2", &"b2"]
+    );
+    assert_eq!(relation.postdom_upper_bound(&"a", &"b"), Some(&"x"));
+}
+
+#[test]
+fn pdub_lub() {
+    // a -> a1 -> x
+    //            ^
+    //            |
+    // b -> b1 ---+
+    let mut relation = TransitiveRelation::new();
+    relation.add("a", "a1");
+    relation.add("b", "b1");
+    relation.add("a1", "x");
+    relation.add("b1", "x");
+
+    assert_eq!(relation.minimal_upper_bounds(&"a", &"b"), vec![&"x"]);
+    assert_eq!(relation.postdom_upper_bound(&"a", &"b"), Some(&"x"));
+}
+
+#[test]
+fn pdub_none() {
+    // a -> a1
+    // b -> b1
+    let mut relation = TransitiveRelation::new();
+    relation.add("a", "a1");
+    relation.add("b", "b1");
+
+    assert!(relation.minimal_upper_bounds(&"a", &"b").is_empty());
+    assert_eq!(relation.postdom_upper_bound(&"a", &"b"), None);
+    assert_eq!(relation.postdom_upper_bound(&"a", &"z"), None);
+}
@@ -8,6 +8,10 @@
 //! Since the `*Storage` variants do not have an undo log `with_log` must be called with the
 //! unified log before any mutating actions.
 
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 /// A trait which allows undo actions (`T`) to be pushed which can be used to rollback actio at a
 /// later time if needed.
 ///
@@ -93,6 +97,168 @@ pub trait Snapshots<T>: UndoLogs<T> {
 
     /// Commit: keep the changes that have been made since the snapshot began
     fn commit(&mut self, snapshot: Self::Snapshot);
+
+    /// Checks that `snapshot` is the innermost open snapshot of this log, without consuming it.
+    ///
+    /// The default implementation cannot tell snapshots apart and accepts all of them, so that
+    /// for a log that does not override it, `try_rollback_to` and `try_commit` **panic** on a
+    /// misused snapshot, just like `rollback_to` and `commit`.
+    fn check_snapshot(&self, snapshot: &Self::Snapshot) -> Result<(), SnapshotError> {
+        let _ = snapshot;
+        Ok(())
+    }
+
+    /// Like `rollback_to`, but reports a snapshot that is not the innermost open snapshot of
+    /// this log as an error instead of panicking. The error hands the snapshot back, so that it
+    /// can still be closed once the snapshots taken after it are.
+    ///
+    /// This is only as fallible as `check_snapshot`: see there.
+    fn try_rollback_to<R>(
+        &mut self,
+        storage: impl FnOnce() -> R,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)>
+    where
+        R: Rollback<T>,
+    {
+        if let Err(err) = self.check_snapshot(&snapshot) {
+            return Err((snapshot, err));
+        }
+        self.rollback_to(storage, snapshot);
+        Ok(())
+    }
+
+    /// Like `commit`, but reports a snapshot that is not the innermost open snapshot of this log
+    /// as an error instead of panicking. The error hands the snapshot back, as with
+    /// `try_rollback_to`.
+    ///
+    /// This is only as fallible as `check_snapshot`: see there.
+    fn try_commit(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)> {
+        if let Err(err) = self.check_snapshot(&snapshot) {
+            return Err((snapshot, err));
+        }
+        self.commit(snapshot);
+        Ok(())
+    }
+
+    /// Runs `f` inside a snapshot that is always rolled back, undoing
+    /// whatever `f` did to `storage`, and returns its result. `f` gets
+    /// both the log and the storage, so that it can make changes through
+    /// e.g. `with_log`.
+    fn probe<S, R>(&mut self, storage: &mut S, f: impl FnOnce(&mut Self, &mut S) -> R) -> R
+    where
+        S: Rollback<T>,
+    {
+        let snapshot = self.start_snapshot();
+        let result = f(self, storage);
+        self.rollback_to(|| storage, snapshot);
+        result
+    }
+
+    /// Runs `f` inside a snapshot, which is committed if `f` returns
+    /// `Ok` and rolled back if it returns `Err`.
+    fn commit_if_ok<S, U, E>(
+        &mut self,
+        storage: &mut S,
+        f: impl FnOnce(&mut Self, &mut S) -> Result<U, E>,
+    ) -> Result<U, E>
+    where
+        S: Rollback<T>,
+    {
+        let snapshot = self.start_snapshot();
+        let result = f(self, storage);
+        if result.is_ok() {
+            self.commit(snapshot);
+        } else {
+            self.rollback_to(|| storage, snapshot);
+        }
+        result
+    }
+
+    /// Runs `f` inside a snapshot that is always committed.
+    fn commit_unconditionally<S, R>(
+        &mut self,
+        storage: &mut S,
+        f: impl FnOnce(&mut Self, &mut S) -> R,
+    ) -> R {
+        let snapshot = self.start_snapshot();
+        let result = f(self, storage);
+        self.commit(snapshot);
+        result
+    }
+
+    /// Starts a new snapshot and returns a guard that derefs to the log
+    /// and rolls `storage` back when dropped, unless
+    /// `SnapshotGuard::commit` is called first.
+    fn snapshot_guard<'a, S>(&'a mut self, storage: &'a mut S) -> SnapshotGuard<'a, T, Self, S>
+    where
+        Self: Sized,
+        S: Rollback<T>,
+    {
+        let snapshot = self.start_snapshot();
+        SnapshotGuard {
+            log: self,
+            storage,
+            snapshot: Some(snapshot),
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+/// A snapshot of an undo log that is rolled back when dropped. See
+/// `Snapshots::snapshot_guard`.
+pub struct SnapshotGuard<'a, T, L: Snapshots<T>, S: Rollback<T>> {
+    log: &'a mut L,
+    storage: &'a mut S,
+    // Only `None` once the snapshot has been committed or rolled back.
+    snapshot: Option<L::Snapshot>,
+    marker: std::marker::PhantomData<fn(T)>,
+}
+
+impl<'a, T, L: Snapshots<T>, S: Rollback<T>> SnapshotGuard<'a, T, L, S> {
+    /// Returns the log and the storage, e.g. to call `with_log`.
+    pub fn parts(&mut self) -> (&mut L, &mut S) {
+        (self.log, self.storage)
+    }
+
+    /// Commits all changes made since the guard was created.
+    pub fn commit(mut self) {
+        let snapshot = self.snapshot.take().unwrap();
+        self.log.commit(snapshot);
+    }
+
+    /// Rolls back all changes made since the guard was created, just
+    /// like dropping the guard.
+    pub fn rollback(mut self) {
+        let snapshot = self.snapshot.take().unwrap();
+        let storage = &mut *self.storage;
+        self.log.rollback_to(|| storage, snapshot);
+    }
+}
+
+impl<'a, T, L: Snapshots<T>, S: Rollback<T>> std::ops::Deref for SnapshotGuard<'a, T, L, S> {
+    type Target = L;
+    fn deref(&self) -> &L {
+        self.log
+    }
+}
+
+impl<'a, T, L: Snapshots<T>, S: Rollback<T>> std::ops::DerefMut for SnapshotGuard<'a, T, L, S> {
+    fn deref_mut(&mut self) -> &mut L {
+        self.log
+    }
+}
+
+impl<'a, T, L: Snapshots<T>, S: Rollback<T>> Drop for SnapshotGuard<'a, T, L, S> {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            let storage = &mut *self.storage;
+            self.log.rollback_to(|| storage, snapshot);
+        }
+    }
 }
 
 impl<T, U> Snapshots<T> for &'_ mut U
@@ -120,6 +286,28 @@ where
     fn commit(&mut self, snapshot: Self::Snapshot) {
         U::commit(self, snapshot)
     }
+
+    fn check_snapshot(&self, snapshot: &Self::Snapshot) -> Result<(), SnapshotError> {
+        U::check_snapshot(self, snapshot)
+    }
+
+    fn try_rollback_to<R>(
+        &mut self,
+        storage: impl FnOnce() -> R,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)>
+    where
+        R: Rollback<T>,
+    {
+        U::try_rollback_to(self, storage, snapshot)
+    }
+
+    fn try_commit(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)> {
+        U::try_commit(self, snapshot)
+    }
 }
 
 pub struct NoUndo;
@@ -131,11 +319,32 @@ impl<T> UndoLogs<T> for NoUndo {
     fn clear(&mut self) {}
 }
 
+/// Source of the ids that tie snapshots to the `VecLog` they were taken from.
+static NEXT_LOG_ID: AtomicUsize = AtomicUsize::new(0);
+
 /// A basic undo log.
-#[derive(Clone, Debug)]
+///
+/// Snapshots taken from a `VecLog` remember which log they belong to and how deeply they are
+/// nested, so that `rollback_to` and `commit` can reject any snapshot but the innermost open
+/// one. A clone of a log gets an id of its own, so it rejects the snapshots of the original.
+#[derive(Debug)]
 pub struct VecLog<T> {
     log: Vec<T>,
     num_open_snapshots: usize,
+    id: usize,
+    // Incremented by `clear`, which closes all open snapshots.
+    epoch: usize,
+}
+
+impl<T: Clone> Clone for VecLog<T> {
+    fn clone(&self) -> Self {
+        VecLog {
+            log: self.log.clone(),
+            num_open_snapshots: self.num_open_snapshots,
+            id: NEXT_LOG_ID.fetch_add(1, Ordering::Relaxed),
+            epoch: self.epoch,
+        }
+    }
 }
 
 impl<T> Default for VecLog<T> {
@@ -143,6 +352,8 @@ impl<T> Default for VecLog<T> {
         VecLog {
             log: Vec::new(),
             num_open_snapshots: 0,
+            id: NEXT_LOG_ID.fetch_add(1, Ordering::Relaxed),
+            epoch: 0,
         }
     }
 }
@@ -157,6 +368,7 @@ impl<T> UndoLogs<T> for VecLog<T> {
     fn clear(&mut self) {
         self.log.clear();
         self.num_open_snapshots = 0;
+        self.epoch += 1;
     }
 }
 
@@ -174,16 +386,59 @@ impl<T> Snapshots<T> for VecLog<T> {
         self.num_open_snapshots += 1;
         Snapshot {
             undo_len: self.log.len(),
+            depth: self.num_open_snapshots,
+            log_id: self.id,
+            epoch: self.epoch,
         }
     }
 
     fn rollback_to<R>(&mut self, values: impl FnOnce() -> R, snapshot: Snapshot)
+    where
+        R: Rollback<T>,
+    {
+        if let Err((_, err)) = self.try_rollback_to(values, snapshot) {
+            panic!("rollback_to: {}", err);
+        }
+    }
+
+    fn commit(&mut self, snapshot: Snapshot) {
+        if let Err((_, err)) = self.try_commit(snapshot) {
+            panic!("commit: {}", err);
+        }
+    }
+
+    /// Checks that `snapshot` is the innermost open snapshot of this log. Failures here
+    /// indicate a failure to follow a stack discipline.
+    fn check_snapshot(&self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.log_id != self.id {
+            return Err(SnapshotError::Foreign);
+        }
+        if snapshot.epoch != self.epoch || snapshot.depth > self.num_open_snapshots {
+            return Err(SnapshotError::NotOpen);
+        }
+        if snapshot.depth < self.num_open_snapshots {
+            return Err(SnapshotError::OutOfOrder {
+                depth: snapshot.depth,
+                innermost: self.num_open_snapshots,
+            });
+        }
+        debug_assert!(self.log.len() >= snapshot.undo_len);
+        Ok(())
+    }
+
+    fn try_rollback_to<R>(
+        &mut self,
+        values: impl FnOnce() -> R,
+        snapshot: Snapshot,
+    ) -> Result<(), (Snapshot, SnapshotError)>
     where
         R: Rollback<T>,
     {
         debug!("rollback_to({})", snapshot.undo_len);
 
-        self.assert_open_snapshot(&snapshot);
+        if let Err(err) = self.check_snapshot(&snapshot) {
+            return Err((snapshot, err));
+        }
 
         if self.log.len() > snapshot.undo_len {
             let mut values = values();
@@ -193,12 +448,15 @@ impl<T> Snapshots<T> for VecLog<T> {
         }
 
         self.num_open_snapshots -= 1;
+        Ok(())
     }
 
-    fn commit(&mut self, snapshot: Snapshot) {
+    fn try_commit(&mut self, snapshot: Snapshot) -> Result<(), (Snapshot, SnapshotError)> {
         debug!("commit({})", snapshot.undo_len);
 
-        self.assert_open_snapshot(&snapshot);
+        if let Err(err) = self.check_snapshot(&snapshot) {
+            return Err((snapshot, err));
+        }
 
         if self.num_open_snapshots == 1 {
             // The root snapshot. It's safe to clear the undo log because
@@ -209,14 +467,44 @@ impl<T> Snapshots<T> for VecLog<T> {
         }
 
         self.num_open_snapshots -= 1;
+        Ok(())
     }
 }
 
-impl<T> VecLog<T> {
-    fn assert_open_snapshot(&self, snapshot: &Snapshot) {
-        // Failures here may indicate a failure to follow a stack discipline.
-        assert!(self.log.len() >= snapshot.undo_len);
-        assert!(self.num_open_snapshots > 0);
+/// An undo log is only serializable when no snapshot is open, at which
+/// point it is empty; it is serialized as a unit struct.
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for VecLog<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.num_open_snapshots > 0 {
+            return Err(serde::ser::Error::custom(
+                "cannot serialize an undo log while snapshots are open",
+            ));
+        }
+        debug_assert!(self.log.is_empty());
+        serializer.serialize_unit_struct("VecLog")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for VecLog<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VecLogVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for VecLogVisitor {
+            type Value = ();
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("unit struct VecLog")
+            }
+
+            fn visit_unit<E: serde::de::Error>(self) -> Result<(), E> {
+                Ok(())
+            }
+        }
+
+        deserializer.deserialize_unit_struct("VecLog", VecLogVisitor)?;
+        Ok(VecLog::default())
     }
 }
 
@@ -245,4 +533,41 @@ where
 pub struct Snapshot {
     // Length of the undo log at the time the snapshot was taken.
     undo_len: usize,
+    // Number of open snapshots, including this one, when it was taken.
+    depth: usize,
+    // Id and epoch of the log the snapshot was taken from.
+    log_id: usize,
+    epoch: usize,
+}
+
+/// Reasons why a snapshot cannot be rolled back or committed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The snapshot was taken from a different undo log, or from a
+    /// different `Persistent` store.
+    Foreign,
+    /// The snapshot is no longer open, e.g. because the log has been
+    /// cleared since it was taken.
+    NotOpen,
+    /// Snapshots taken after this one are still open; they must be
+    /// rolled back or committed first. `depth` is the nesting depth of
+    /// the snapshot (1 for the outermost one) and `innermost` that of
+    /// the innermost open snapshot.
+    OutOfOrder { depth: usize, innermost: usize },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SnapshotError::Foreign => write!(fmt, "snapshot was taken from a different undo log"),
+            SnapshotError::NotOpen => write!(fmt, "snapshot is no longer open"),
+            SnapshotError::OutOfOrder { depth, innermost } => write!(
+                fmt,
+                "snapshot at depth {} used while the snapshot at depth {} is still open",
+                depth, innermost
+            ),
+        }
+    }
 }
+
+impl Error for SnapshotError {}
@@ -1,41 +1,117 @@
-#[cfg(feature = "persistent")]
-use dogged::DVec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use snapshot_vec as sv;
 use std::marker::PhantomData;
+use std::mem;
 use std::ops::{self, Range};
+#[cfg(feature = "persistent")]
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use undo_log::{Rollback, Snapshots, UndoLogs, VecLog};
-
-use super::{UnifyKey, UnifyValue, VarValue};
+use undo_log::{Rollback, SnapshotError, Snapshots, UndoLogs, VecLog};
 
-#[allow(dead_code)] // rustc BUG
-#[allow(type_alias_bounds)]
-type Key<S: UnificationStoreBase> = <S as UnificationStoreBase>::Key;
+#[cfg(feature = "persistent")]
+use super::persistent_vec::PersistentVec;
+use super::{key_from_usize, UnifyKey, UnifyValue, VarValue};
 
 /// Largely internal trait implemented by the unification table
 /// backing store types. The most common such type is `InPlace`,
 /// which indicates a standard, mutable unification table.
-pub trait UnificationStoreBase: ops::Index<usize, Output = VarValue<Key<Self>>> {
+///
+/// The fields of each key are read one at a time, so that a store is
+/// free to lay them out separately; see `Packed`.
+///
+/// **Breaking change:** this trait used to require
+/// `ops::Index<usize, Output = VarValue<Self::Key>>`, which a store that
+/// splits its fields cannot provide. Code that indexed a store directly
+/// must use `parent`, `rank` and `value` instead. `InPlace` and
+/// `Persistent` still implement `Index`.
+pub trait UnificationStoreBase {
     type Key: UnifyKey<Value = Self::Value>;
     type Value: UnifyValue;
 
     fn len(&self) -> usize;
 
+    /// Returns the parent of the key at `index`; a root is its own
+    /// parent.
+    fn parent(&self, index: usize) -> Self::Key;
+
+    /// Returns the rank of the key at `index`; only meaningful for
+    /// roots.
+    fn rank(&self, index: usize) -> u32;
+
+    /// Returns the value of the key at `index`; only meaningful for
+    /// roots.
+    fn value(&self, index: usize) -> &Self::Value;
+
+    /// Returns the next member of the class of the key at `index`.
+    #[cfg(feature = "class-members")]
+    fn next(&self, index: usize) -> Self::Key;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     fn tag() -> &'static str {
         Self::Key::tag()
     }
 }
 
 pub trait UnificationStoreMut: UnificationStoreBase {
-    fn reset_unifications(&mut self, value: impl FnMut(u32) -> VarValue<Self::Key>);
+    /// True if a snapshot is open, so that changes may be rolled back.
+    fn in_snapshot(&self) -> bool;
+
+    fn reset_unifications(&mut self, value: impl FnMut(usize) -> VarValue<Self::Key>);
 
     fn push(&mut self, value: VarValue<Self::Key>);
 
+    /// Pushes every value of `values`. Stores with an undo log record a
+    /// single entry for all of them.
+    fn push_all(&mut self, values: impl IntoIterator<Item = VarValue<Self::Key>>);
+
     fn reserve(&mut self, num_new_values: usize);
 
     fn update<F>(&mut self, index: usize, op: F)
     where
         F: FnOnce(&mut VarValue<Self::Key>);
+
+    /// Sets the parent of the key at `index`.
+    ///
+    /// The `set_` methods each write a single field. By default they go
+    /// through `update`, which may save the whole `VarValue`, value
+    /// included, in case of a rollback; stores with an undo log
+    /// override them to save only the old contents of the field.
+    #[inline]
+    fn set_parent(&mut self, index: usize, parent: Self::Key) {
+        self.update(index, |value| value.parent = parent);
+    }
+
+    /// Sets the rank of the key at `index`.
+    #[inline]
+    fn set_rank(&mut self, index: usize, rank: u32) {
+        self.update(index, |value| value.rank = rank);
+    }
+
+    /// Replaces the value of the key at `index`.
+    #[inline]
+    fn set_value(&mut self, index: usize, value: Self::Value) {
+        self.update(index, |slot| slot.value = value);
+    }
+
+    /// Sets the next member of the class of the key at `index`.
+    #[cfg(feature = "class-members")]
+    #[inline]
+    fn set_next(&mut self, index: usize, next: Self::Key) {
+        self.update(index, |value| value.next = next);
+    }
+
+    /// Points the key at `index` at `ancestor`, one of the keys above
+    /// its current parent, as part of path compression. This never
+    /// changes the result of a `find`, so stores for which the write is
+    /// costly may skip it.
+    #[inline]
+    fn compress_path(&mut self, index: usize, ancestor: Self::Key) {
+        self.set_parent(index, ancestor);
+    }
 }
 
 pub trait UnificationStore: UnificationStoreMut {
@@ -47,12 +123,34 @@ pub trait UnificationStore: UnificationStoreMut {
 
     fn commit(&mut self, snapshot: Self::Snapshot);
 
+    /// Like `rollback_to`, but hands `snapshot` back with an error if it
+    /// is not the innermost open snapshot, as far as the store can tell.
+    fn try_rollback_to(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)>;
+
+    /// Like `commit`, but hands `snapshot` back with an error if it is
+    /// not the innermost open snapshot, as far as the store can tell.
+    fn try_commit(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)>;
+
     fn values_since_snapshot(&self, snapshot: &Self::Snapshot) -> Range<usize>;
 }
 
 /// Backing store for an in-place unification table.
 /// Not typically used directly.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "V: Serialize, L: Serialize",
+        deserialize = "V: Deserialize<'de>, L: Deserialize<'de>"
+    ))
+)]
 pub struct InPlace<
     K: UnifyKey,
     V: sv::VecLike<Delegate<K>> = Vec<VarValue<K>>,
@@ -81,6 +179,27 @@ where
     fn len(&self) -> usize {
         self.values.len()
     }
+
+    #[inline]
+    fn parent(&self, index: usize) -> K {
+        self.values[index].parent
+    }
+
+    #[inline]
+    fn rank(&self, index: usize) -> u32 {
+        self.values[index].rank
+    }
+
+    #[inline]
+    fn value(&self, index: usize) -> &K::Value {
+        &self.values[index].value
+    }
+
+    #[cfg(feature = "class-members")]
+    #[inline]
+    fn next(&self, index: usize) -> K {
+        self.values[index].next
+    }
 }
 
 impl<K, V, L> UnificationStoreMut for InPlace<K, V, L>
@@ -90,8 +209,13 @@ where
     L: UndoLogs<sv::UndoLog<Delegate<K>>>,
 {
     #[inline]
-    fn reset_unifications(&mut self, mut value: impl FnMut(u32) -> VarValue<Self::Key>) {
-        self.values.set_all(|i| value(i as u32));
+    fn in_snapshot(&self) -> bool {
+        self.values.in_snapshot()
+    }
+
+    #[inline]
+    fn reset_unifications(&mut self, value: impl FnMut(usize) -> VarValue<Self::Key>) {
+        self.values.set_all(value);
     }
 
     #[inline]
@@ -99,6 +223,11 @@ where
         self.values.push(value);
     }
 
+    #[inline]
+    fn push_all(&mut self, values: impl IntoIterator<Item = VarValue<Self::Key>>) {
+        self.values.push_all(values);
+    }
+
     #[inline]
     fn reserve(&mut self, num_new_values: usize) {
         self.values.reserve(num_new_values);
@@ -111,6 +240,35 @@ where
     {
         self.values.update(index, op)
     }
+
+    #[inline]
+    fn set_parent(&mut self, index: usize, parent: K) {
+        self.values.update_with_undo(index, |value| {
+            VarValueUndo::Parent(index, mem::replace(&mut value.parent, parent))
+        });
+    }
+
+    #[inline]
+    fn set_rank(&mut self, index: usize, rank: u32) {
+        self.values.update_with_undo(index, |value| {
+            VarValueUndo::Rank(index, mem::replace(&mut value.rank, rank))
+        });
+    }
+
+    #[inline]
+    fn set_value(&mut self, index: usize, value: K::Value) {
+        self.values.update_with_undo(index, |slot| {
+            VarValueUndo::Value(index, mem::replace(&mut slot.value, value))
+        });
+    }
+
+    #[cfg(feature = "class-members")]
+    #[inline]
+    fn set_next(&mut self, index: usize, next: K) {
+        self.values.update_with_undo(index, |value| {
+            VarValueUndo::Next(index, mem::replace(&mut value.next, next))
+        });
+    }
 }
 
 impl<K, V, L> UnificationStore for InPlace<K, V, L>
@@ -136,6 +294,22 @@ where
         self.values.commit(snapshot);
     }
 
+    #[inline]
+    fn try_rollback_to(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)> {
+        self.values.try_rollback_to(snapshot)
+    }
+
+    #[inline]
+    fn try_commit(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)> {
+        self.values.try_commit(snapshot)
+    }
+
     #[inline]
     fn values_since_snapshot(&self, snapshot: &Self::Snapshot) -> Range<usize> {
         snapshot.value_count..self.len()
@@ -159,9 +333,30 @@ pub struct Delegate<K>(PhantomData<K>);
 
 impl<K: UnifyKey> sv::SnapshotVecDelegate for Delegate<K> {
     type Value = VarValue<K>;
-    type Undo = ();
+    type Undo = VarValueUndo<K>;
+
+    fn reverse(values: &mut Vec<VarValue<K>>, undo: VarValueUndo<K>) {
+        match undo {
+            VarValueUndo::Parent(index, parent) => values[index].parent = parent,
+            VarValueUndo::Rank(index, rank) => values[index].rank = rank,
+            VarValueUndo::Value(index, value) => values[index].value = value,
+            #[cfg(feature = "class-members")]
+            VarValueUndo::Next(index, next) => values[index].next = next,
+        }
+    }
+}
 
-    fn reverse(_: &mut Vec<VarValue<K>>, _: ()) {}
+/// Undo actions of an `InPlace` store that restore a single field of
+/// the `VarValue` at the given index, so that path compression and
+/// unions do not have to clone the value of the key.
+#[doc(hidden)]
+#[derive(Clone, Debug)]
+pub enum VarValueUndo<K: UnifyKey> {
+    Parent(usize, K),
+    Rank(usize, u32),
+    Value(usize, K::Value),
+    #[cfg(feature = "class-members")]
+    Next(usize, K),
 }
 
 impl<K: UnifyKey> Rollback<sv::UndoLog<Delegate<K>>> for super::UnificationTableStorage<K> {
@@ -170,10 +365,359 @@ impl<K: UnifyKey> Rollback<sv::UndoLog<Delegate<K>>> for super::UnificationTable
     }
 }
 
-#[cfg(feature = "persistent")]
+/// Backing store for an in-place unification table that keeps the
+/// parent of each key apart from its value. Not typically used
+/// directly.
+///
+/// The parent and rank of each key are packed into a single `u64`, the
+/// parent index in the low half and the rank in the high half, and
+/// these words are kept in one dense array. `find` only ever reads that
+/// array, so it does not pull the values of the keys it walks past into
+/// the cache. The values (and, with the `class-members` feature, the
+/// member lists) are kept in a second array. Snapshots behave as with
+/// `InPlace`. Key indices must fit in a `u32`, so the index type of
+/// the key must be `u16` or `u32`.
 #[derive(Clone, Debug)]
+pub struct Packed<K: UnifyKey>
+where
+    K::Index: Into<u32>,
+{
+    parents: sv::SnapshotVec<PackedParents>,
+    slots: sv::SnapshotVec<PackedSlots<K>>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PackedParents;
+
+impl sv::SnapshotVecDelegate for PackedParents {
+    type Value = u64;
+    type Undo = ();
+
+    fn reverse(_: &mut Vec<u64>, _: ()) {}
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PackedSlots<K>(PhantomData<K>);
+
+impl<K: UnifyKey> sv::SnapshotVecDelegate for PackedSlots<K> {
+    type Value = PackedSlot<K>;
+    type Undo = PackedSlotUndo<K>;
+
+    fn reverse(slots: &mut Vec<PackedSlot<K>>, undo: PackedSlotUndo<K>) {
+        match undo {
+            PackedSlotUndo::Value(index, value) => slots[index].value = Some(value),
+            #[cfg(feature = "class-members")]
+            PackedSlotUndo::Next(index, next) => slots[index].next = next,
+        }
+    }
+}
+
+// Like `VarValueUndo`, for the fields kept in the slots.
+#[derive(Clone, Debug)]
+enum PackedSlotUndo<K: UnifyKey> {
+    Value(usize, K::Value),
+    #[cfg(feature = "class-members")]
+    Next(usize, K),
+}
+
+#[derive(Clone, Debug)]
+struct PackedSlot<K: UnifyKey> {
+    // Only `None` while `update` has moved the value out.
+    value: Option<K::Value>,
+    #[cfg(feature = "class-members")]
+    next: K,
+}
+
+/// Holds the value of a slot while `Packed::update` runs, and puts it
+/// back if the update panics, so that the slot is never left empty.
+struct SlotGuard<'a, K: UnifyKey> {
+    slot: &'a mut PackedSlot<K>,
+    value: Option<VarValue<K>>,
+}
+
+impl<'a, K: UnifyKey> Drop for SlotGuard<'a, K> {
+    fn drop(&mut self) {
+        if let Some(value) = self.value.take() {
+            self.slot.value = Some(value.value);
+        }
+    }
+}
+
+// HACK(eddyb) manual impl avoids `Default` bound on `K`.
+impl<K: UnifyKey> Default for Packed<K>
+where
+    K::Index: Into<u32>,
+{
+    fn default() -> Self {
+        Packed {
+            parents: sv::SnapshotVec::new(),
+            slots: sv::SnapshotVec::new(),
+        }
+    }
+}
+
+impl<K: UnifyKey> Packed<K>
+where
+    K::Index: Into<u32>,
+{
+    fn pack(parent: K, rank: u32) -> u64 {
+        (u64::from(rank) << 32) | u64::from(parent.index().into())
+    }
+
+    fn split(value: VarValue<K>) -> (u64, PackedSlot<K>) {
+        let word = Self::pack(value.parent, value.rank);
+        let slot = PackedSlot {
+            value: Some(value.value),
+            #[cfg(feature = "class-members")]
+            next: value.next,
+        };
+        (word, slot)
+    }
+}
+
+impl<K: UnifyKey> UnificationStoreBase for Packed<K>
+where
+    K::Index: Into<u32>,
+{
+    type Key = K;
+    type Value = K::Value;
+
+    fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    #[inline]
+    fn parent(&self, index: usize) -> K {
+        key_from_usize(self.parents[index] as u32 as usize)
+    }
+
+    #[inline]
+    fn rank(&self, index: usize) -> u32 {
+        (self.parents[index] >> 32) as u32
+    }
+
+    #[inline]
+    fn value(&self, index: usize) -> &K::Value {
+        self.slots[index].value.as_ref().unwrap()
+    }
+
+    #[cfg(feature = "class-members")]
+    #[inline]
+    fn next(&self, index: usize) -> K {
+        self.slots[index].next
+    }
+}
+
+impl<K: UnifyKey> UnificationStoreMut for Packed<K>
+where
+    K::Index: Into<u32>,
+{
+    #[inline]
+    fn in_snapshot(&self) -> bool {
+        self.parents.in_snapshot()
+    }
+
+    fn reset_unifications(&mut self, value: impl FnMut(usize) -> VarValue<Self::Key>) {
+        let (words, slots): (Vec<_>, Vec<_>) = (0..self.len()).map(value).map(Self::split).unzip();
+        let (mut words, mut slots) = (words.into_iter(), slots.into_iter());
+        self.parents.set_all(|_| words.next().unwrap());
+        self.slots.set_all(|_| slots.next().unwrap());
+    }
+
+    #[inline]
+    fn push(&mut self, value: VarValue<Self::Key>) {
+        let (word, slot) = Self::split(value);
+        self.parents.push(word);
+        self.slots.push(slot);
+    }
+
+    fn push_all(&mut self, values: impl IntoIterator<Item = VarValue<Self::Key>>) {
+        let (words, slots): (Vec<_>, Vec<_>) = values.into_iter().map(Self::split).unzip();
+        self.parents.push_all(words);
+        self.slots.push_all(slots);
+    }
+
+    #[inline]
+    fn reserve(&mut self, num_new_values: usize) {
+        self.parents.reserve(num_new_values);
+        self.slots.reserve(num_new_values);
+    }
+
+    fn update<F>(&mut self, index: usize, op: F)
+    where
+        F: FnOnce(&mut VarValue<Self::Key>),
+    {
+        // `op` may change the value in place, so a snapshot needs a
+        // copy of the old one. Outside of snapshots, the value is only
+        // moved out of its slot and back.
+        if self.slots.in_snapshot() {
+            let old_value = self.value(index).clone();
+            self.slots.record(PackedSlotUndo::Value(index, old_value));
+            #[cfg(feature = "class-members")]
+            {
+                let old_next = self.next(index);
+                self.slots.record(PackedSlotUndo::Next(index, old_next));
+            }
+        }
+
+        let old_word = self.parents[index];
+        let slot = self.slots.get_mut(index);
+        let mut guard = SlotGuard {
+            value: Some(VarValue {
+                parent: key_from_usize(old_word as u32 as usize),
+                value: slot.value.take().unwrap(),
+                rank: (old_word >> 32) as u32,
+                #[cfg(feature = "class-members")]
+                next: slot.next,
+            }),
+            slot,
+        };
+        op(guard.value.as_mut().unwrap());
+        let value = guard.value.take().unwrap();
+        guard.slot.value = Some(value.value);
+        #[cfg(feature = "class-members")]
+        {
+            guard.slot.next = value.next;
+        }
+
+        let new_word = Self::pack(value.parent, value.rank);
+        if new_word != old_word {
+            self.parents.set(index, new_word);
+        }
+    }
+
+    #[inline]
+    fn set_parent(&mut self, index: usize, parent: K) {
+        let rank = self.rank(index);
+        self.parents.set(index, Self::pack(parent, rank));
+    }
+
+    #[inline]
+    fn set_rank(&mut self, index: usize, rank: u32) {
+        let parent = self.parent(index);
+        self.parents.set(index, Self::pack(parent, rank));
+    }
+
+    #[inline]
+    fn set_value(&mut self, index: usize, value: K::Value) {
+        self.slots.update_with_undo(index, |slot| {
+            let old_value = slot.value.replace(value).unwrap();
+            PackedSlotUndo::Value(index, old_value)
+        });
+    }
+
+    #[cfg(feature = "class-members")]
+    #[inline]
+    fn set_next(&mut self, index: usize, next: K) {
+        self.slots.update_with_undo(index, |slot| {
+            PackedSlotUndo::Next(index, mem::replace(&mut slot.next, next))
+        });
+    }
+}
+
+impl<K: UnifyKey> UnificationStore for Packed<K>
+where
+    K::Index: Into<u32>,
+{
+    type Snapshot = (sv::Snapshot, sv::Snapshot);
+
+    #[inline]
+    fn start_snapshot(&mut self) -> Self::Snapshot {
+        (self.parents.start_snapshot(), self.slots.start_snapshot())
+    }
+
+    #[inline]
+    fn rollback_to(&mut self, snapshot: Self::Snapshot) {
+        self.parents.rollback_to(snapshot.0);
+        self.slots.rollback_to(snapshot.1);
+    }
+
+    #[inline]
+    fn commit(&mut self, snapshot: Self::Snapshot) {
+        self.parents.commit(snapshot.0);
+        self.slots.commit(snapshot.1);
+    }
+
+    #[inline]
+    fn try_rollback_to(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)> {
+        // Check both halves before closing either, in case they were
+        // taken from different stores.
+        if let Err(err) = self.slots.check_snapshot(&snapshot.1) {
+            return Err((snapshot, err));
+        }
+        let (parents, slots) = snapshot;
+        match self.parents.try_rollback_to(parents) {
+            Ok(()) => {
+                self.slots.rollback_to(slots);
+                Ok(())
+            }
+            Err((parents, err)) => Err(((parents, slots), err)),
+        }
+    }
+
+    #[inline]
+    fn try_commit(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)> {
+        if let Err(err) = self.slots.check_snapshot(&snapshot.1) {
+            return Err((snapshot, err));
+        }
+        let (parents, slots) = snapshot;
+        match self.parents.try_commit(parents) {
+            Ok(()) => {
+                self.slots.commit(slots);
+                Ok(())
+            }
+            Err((parents, err)) => Err(((parents, slots), err)),
+        }
+    }
+
+    #[inline]
+    fn values_since_snapshot(&self, snapshot: &Self::Snapshot) -> Range<usize> {
+        snapshot.0.value_count..self.len()
+    }
+}
+
+/// Backing store for a persistent unification table, in which taking a
+/// snapshot is O(1). Not typically used directly.
+///
+/// The values are kept in a persistent vector whose nodes are shared
+/// with the snapshots that are still open. Nodes that are not shared
+/// are updated in place, so between snapshots the table behaves much
+/// like an in-place one. While a snapshot is open, path compression is
+/// skipped for keys whose node is still shared with it, rather than
+/// copying that node; it happens on a later `find` once the snapshot is
+/// closed.
+///
+/// As with `VecLog`, each store has an id that its snapshots carry, so
+/// snapshots of other stores are rejected. A clone of a store gets an
+/// id of its own, so it does not accept the snapshots of the original.
+#[cfg(feature = "persistent")]
+#[derive(Debug)]
 pub struct Persistent<K: UnifyKey> {
-    values: DVec<VarValue<K>>,
+    values: PersistentVec<VarValue<K>>,
+    num_open_snapshots: usize,
+    id: usize,
+}
+
+/// Source of the ids that tie snapshots to the `Persistent` store they
+/// were taken from.
+#[cfg(feature = "persistent")]
+static NEXT_STORE_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "persistent")]
+impl<K: UnifyKey> Clone for Persistent<K> {
+    fn clone(&self) -> Self {
+        Persistent {
+            values: self.values.clone(),
+            num_open_snapshots: self.num_open_snapshots,
+            id: NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
 }
 
 // HACK(eddyb) manual impl avoids `Default` bound on `K`.
@@ -181,7 +725,9 @@ pub struct Persistent<K: UnifyKey> {
 impl<K: UnifyKey> Default for Persistent<K> {
     fn default() -> Self {
         Persistent {
-            values: DVec::new(),
+            values: PersistentVec::new(),
+            num_open_snapshots: 0,
+            id: NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed),
         }
     }
 }
@@ -194,18 +740,39 @@ impl<K: UnifyKey> UnificationStoreBase for Persistent<K> {
     fn len(&self) -> usize {
         self.values.len()
     }
+
+    #[inline]
+    fn parent(&self, index: usize) -> K {
+        self.values[index].parent
+    }
+
+    #[inline]
+    fn rank(&self, index: usize) -> u32 {
+        self.values[index].rank
+    }
+
+    #[inline]
+    fn value(&self, index: usize) -> &K::Value {
+        &self.values[index].value
+    }
+
+    #[cfg(feature = "class-members")]
+    #[inline]
+    fn next(&self, index: usize) -> K {
+        self.values[index].next
+    }
 }
 
 #[cfg(feature = "persistent")]
 impl<K: UnifyKey> UnificationStoreMut for Persistent<K> {
     #[inline]
-    fn reset_unifications(&mut self, mut value: impl FnMut(u32) -> VarValue<Self::Key>) {
-        // Without extending dogged, there isn't obviously a more
-        // efficient way to do this. But it's pretty dumb. Maybe
-        // dogged needs a `map`.
-        for i in 0..self.values.len() {
-            self.values[i] = value(i as u32);
-        }
+    fn in_snapshot(&self) -> bool {
+        self.num_open_snapshots > 0
+    }
+
+    #[inline]
+    fn reset_unifications(&mut self, value: impl FnMut(usize) -> VarValue<Self::Key>) {
+        self.values.set_all(value);
     }
 
     #[inline]
@@ -213,9 +780,16 @@ impl<K: UnifyKey> UnificationStoreMut for Persistent<K> {
         self.values.push(value);
     }
 
+    #[inline]
+    fn push_all(&mut self, values: impl IntoIterator<Item = VarValue<Self::Key>>) {
+        for value in values {
+            self.values.push(value);
+        }
+    }
+
     #[inline]
     fn reserve(&mut self, _num_new_values: usize) {
-        // not obviously relevant to DVec.
+        // not obviously relevant to a persistent vector.
     }
 
     #[inline]
@@ -226,6 +800,13 @@ impl<K: UnifyKey> UnificationStoreMut for Persistent<K> {
         let p = &mut self.values[index];
         op(p);
     }
+
+    #[inline]
+    fn compress_path(&mut self, index: usize, ancestor: Self::Key) {
+        if let Some(value) = self.values.get_mut_unshared(index) {
+            value.parent = ancestor;
+        }
+    }
 }
 
 #[cfg(feature = "persistent")]
@@ -234,16 +815,53 @@ impl<K: UnifyKey> UnificationStore for Persistent<K> {
 
     #[inline]
     fn start_snapshot(&mut self) -> Self::Snapshot {
-        self.clone()
+        // Unlike a clone, the snapshot keeps the id of the store.
+        let snapshot = Persistent {
+            values: self.values.clone(),
+            num_open_snapshots: self.num_open_snapshots,
+            id: self.id,
+        };
+        self.num_open_snapshots += 1;
+        snapshot
     }
 
     #[inline]
     fn rollback_to(&mut self, snapshot: Self::Snapshot) {
+        if let Err((_, err)) = self.try_rollback_to(snapshot) {
+            panic!("rollback_to: {}", err);
+        }
+    }
+
+    #[inline]
+    fn commit(&mut self, snapshot: Self::Snapshot) {
+        if let Err((_, err)) = self.try_commit(snapshot) {
+            panic!("commit: {}", err);
+        }
+    }
+
+    #[inline]
+    fn try_rollback_to(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)> {
+        if let Err(err) = self.check_open_snapshot(&snapshot) {
+            return Err((snapshot, err));
+        }
         *self = snapshot;
+        Ok(())
     }
 
     #[inline]
-    fn commit(&mut self, _snapshot: Self::Snapshot) {}
+    fn try_commit(
+        &mut self,
+        snapshot: Self::Snapshot,
+    ) -> Result<(), (Self::Snapshot, SnapshotError)> {
+        if let Err(err) = self.check_open_snapshot(&snapshot) {
+            return Err((snapshot, err));
+        }
+        self.num_open_snapshots -= 1;
+        Ok(())
+    }
 
     #[inline]
     fn values_since_snapshot(&self, snapshot: &Self::Snapshot) -> Range<usize> {
@@ -251,6 +869,29 @@ impl<K: UnifyKey> UnificationStore for Persistent<K> {
     }
 }
 
+#[cfg(feature = "persistent")]
+impl<K: UnifyKey> Persistent<K> {
+    /// A snapshot is a copy of the store from before it was opened, so
+    /// it is the innermost open one if exactly one snapshot was opened
+    /// since.
+    fn check_open_snapshot(&self, snapshot: &Self) -> Result<(), SnapshotError> {
+        if snapshot.id != self.id {
+            return Err(SnapshotError::Foreign);
+        }
+        let depth = snapshot.num_open_snapshots + 1;
+        if depth > self.num_open_snapshots {
+            return Err(SnapshotError::NotOpen);
+        }
+        if depth < self.num_open_snapshots {
+            return Err(SnapshotError::OutOfOrder {
+                depth,
+                innermost: self.num_open_snapshots,
+            });
+        }
+        Ok(())
+    }
+}
+
 #[cfg(feature = "persistent")]
 impl<K> ops::Index<usize> for Persistent<K>
 where
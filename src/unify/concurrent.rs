@@ -0,0 +1,255 @@
+//! A union-find table that can be shared between threads.
+//!
+//! `ConcurrentUnificationTable` keeps each parent pointer in an atomic
+//! integer, so `find` never blocks: it walks up the tree and applies
+//! *path halving* as it goes, making every visited key point at its
+//! grandparent with a compare-and-swap. A failed swap just means that
+//! some other thread already shortened the path.
+//!
+//! The value and rank of each key live behind a per-key lock, which
+//! only matters while the key is a root. Unions lock the two roots (in
+//! index order, to avoid deadlock), check that they are still roots,
+//! and only then merge the values and link one root under the other.
+//! Since roots are only ever redirected under their own lock, holding
+//! the lock of a root keeps it a root.
+//!
+//! Creating keys requires exclusive access, and there is no snapshot
+//! support.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use super::{key_from_usize, NoError, UnifyIndex, UnifyKey, UnifyValue};
+
+/// Table of unification keys that supports `find`, `unify_var_var`
+/// and friends through a shared reference. See the module
+/// documentation for details.
+#[derive(Debug)]
+pub struct ConcurrentUnificationTable<K: UnifyKey> {
+    // Parent indices are stored as `usize`s, whatever the index type
+    // of `K`, since there is no atomic type for every index type.
+    parents: Vec<AtomicUsize>,
+    roots: Vec<Mutex<RootData<K::Value>>>,
+}
+
+/// The data guarded by the lock of a key. Only meaningful while the
+/// key is a root.
+#[derive(Debug)]
+struct RootData<V> {
+    rank: u32,
+    value: V,
+}
+
+// Manual impl avoids `Default` bound on `K`.
+impl<K: UnifyKey> Default for ConcurrentUnificationTable<K> {
+    fn default() -> Self {
+        ConcurrentUnificationTable {
+            parents: Vec::new(),
+            roots: Vec::new(),
+        }
+    }
+}
+
+impl<K: UnifyKey> ConcurrentUnificationTable<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of keys created so far.
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// Creates a fresh key with the given value. Unlike the other
+    /// operations, this requires exclusive access to the table.
+    pub fn new_key(&mut self, value: K::Value) -> K {
+        let len = self.parents.len();
+        let key: K = key_from_usize(len);
+        self.parents.push(AtomicUsize::new(len));
+        self.roots.push(Mutex::new(RootData { rank: 0, value }));
+        debug!("{}: created new key: {:?}", K::tag(), key);
+        key
+    }
+
+    fn parent(&self, key: K) -> K {
+        key_from_usize(self.parents[key.index().as_usize()].load(Ordering::Acquire))
+    }
+
+    fn is_root(&self, key: K) -> bool {
+        self.parent(key) == key
+    }
+
+    fn lock(&self, key: K) -> MutexGuard<'_, RootData<K::Value>> {
+        self.roots[key.index().as_usize()].lock().unwrap()
+    }
+
+    /// Given a key, returns the (current) root key. This performs path
+    /// halving, so it may shorten paths concurrently with other
+    /// threads. By the time it returns, the root may already have been
+    /// redirected by a concurrent union.
+    pub fn find<K1: Into<K>>(&self, id: K1) -> K {
+        let mut key = id.into();
+        loop {
+            let parent = self.parent(key);
+            if parent == key {
+                return key;
+            }
+            let grandparent = self.parent(parent);
+            if grandparent != parent {
+                // Path halving; if this fails, someone else has
+                // already moved `key` closer to the root.
+                let _ = self.parents[key.index().as_usize()].compare_exchange(
+                    parent.index().as_usize(),
+                    grandparent.index().as_usize(),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                );
+            }
+            key = grandparent;
+        }
+    }
+
+    /// Finds the root of `id` and locks it, retrying if the root is
+    /// redirected before the lock is acquired.
+    fn lock_root(&self, id: K) -> (K, MutexGuard<'_, RootData<K::Value>>) {
+        loop {
+            let root = self.find(id);
+            let guard = self.lock(root);
+            if self.is_root(root) {
+                return (root, guard);
+            }
+        }
+    }
+
+    /// Given two keys, indicates whether they have been unioned
+    /// together. The answer may be out of date as soon as it is
+    /// returned, but only in one direction: once two keys are unioned,
+    /// they stay unioned.
+    pub fn unioned<K1: Into<K>, K2: Into<K>>(&self, a_id: K1, b_id: K2) -> bool {
+        let a_id = a_id.into();
+        let b_id = b_id.into();
+        loop {
+            let root_a = self.find(a_id);
+            let root_b = self.find(b_id);
+            if root_a == root_b {
+                return true;
+            }
+            // If `root_a` is still a root, then `a_id` and `b_id` were
+            // in different classes when we found `root_b`.
+            if self.is_root(root_a) {
+                return false;
+            }
+        }
+    }
+
+    /// Returns the current value for the given key.
+    pub fn probe_value<K1: Into<K>>(&self, id: K1) -> K::Value {
+        let (_, guard) = self.lock_root(id.into());
+        guard.value.clone()
+    }
+
+    /// Unions two keys without the possibility of failure; only
+    /// applicable when unify values use `NoError` as their error type.
+    pub fn union<K1, K2>(&self, a_id: K1, b_id: K2)
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+        K::Value: UnifyValue<Error = NoError>,
+    {
+        self.unify_var_var(a_id, b_id).unwrap();
+    }
+
+    /// Unions together two variables, merging their values. If
+    /// merging the values fails, the error is propagated and this
+    /// method has no effect.
+    pub fn unify_var_var<K1, K2>(
+        &self,
+        a_id: K1,
+        b_id: K2,
+    ) -> Result<(), <K::Value as UnifyValue>::Error>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        let a_id = a_id.into();
+        let b_id = b_id.into();
+
+        loop {
+            let root_a = self.find(a_id);
+            let root_b = self.find(b_id);
+            if root_a == root_b {
+                return Ok(());
+            }
+
+            // Always lock the lower index first, so that two threads
+            // unioning the same pair of roots cannot deadlock.
+            let (mut guard_a, mut guard_b);
+            if root_a.index().as_usize() < root_b.index().as_usize() {
+                guard_a = self.lock(root_a);
+                guard_b = self.lock(root_b);
+            } else {
+                guard_b = self.lock(root_b);
+                guard_a = self.lock(root_a);
+            }
+            if !self.is_root(root_a) || !self.is_root(root_b) {
+                // Lost a race with another union; start over.
+                continue;
+            }
+
+            let combined = K::Value::unify_values(&guard_a.value, &guard_b.value)?;
+            debug!("unify(key_a={:?}, key_b={:?})", root_a, root_b);
+
+            let rank_a = guard_a.rank;
+            let rank_b = guard_b.rank;
+            let a_is_new_root = if let Some((new_root, _)) =
+                K::order_roots(root_a, &guard_a.value, root_b, &guard_b.value)
+            {
+                new_root == root_a
+            } else {
+                rank_a > rank_b
+            };
+
+            if a_is_new_root {
+                guard_a.rank = if rank_a > rank_b { rank_a } else { rank_b + 1 };
+                guard_a.value = combined;
+                self.redirect_root(root_b, root_a);
+            } else {
+                guard_b.rank = if rank_b > rank_a { rank_b } else { rank_a + 1 };
+                guard_b.value = combined;
+                self.redirect_root(root_a, root_b);
+            }
+            return Ok(());
+        }
+    }
+
+    /// Sets the value of the key `a_id` to `b`, attempting to merge
+    /// with the previous value.
+    pub fn unify_var_value<K1: Into<K>>(
+        &self,
+        a_id: K1,
+        b: K::Value,
+    ) -> Result<(), <K::Value as UnifyValue>::Error> {
+        let (root, mut guard) = self.lock_root(a_id.into());
+        guard.value = K::Value::unify_values(&guard.value, &b)?;
+        debug!("{}: updated value of {:?}", K::tag(), root);
+        Ok(())
+    }
+
+    /// Redirects `old_root_key` to `new_root_key`. The caller must
+    /// hold the locks of both keys.
+    fn redirect_root(&self, old_root_key: K, new_root_key: K) {
+        debug!(
+            "{}: redirect_root({:?} -> {:?})",
+            K::tag(),
+            old_root_key,
+            new_root_key
+        );
+        self.parents[old_root_key.index().as_usize()]
+            .store(new_root_key.index().as_usize(), Ordering::Release);
+    }
+}
@@ -0,0 +1,367 @@
+//! A unification table that can explain *why* two keys are unioned.
+//!
+//! Alongside the ordinary union-find table we maintain a "proof
+//! forest" (as described in "Proof-Producing Congruence Closure" by
+//! Nieuwenhuis and Oliveras, RTA 2005). Every union that actually
+//! joins two classes adds an edge between the two keys that were
+//! passed to it, labeled with a user-supplied justification. The
+//! forest has exactly one path between any two unioned keys, and the
+//! labels along that path are the explanation.
+//!
+//! Unlike the union-find parent pointers, proof edges are never
+//! compressed, so adding an edge must first re-root one of the two
+//! trees. We re-root the tree of the smaller class, so that a key is
+//! moved O(log n) times overall, and move the justifications along
+//! rather than cloning them. All edge updates are recorded in an undo
+//! log, so proofs are rolled back together with the unions they
+//! justify.
+
+use std::marker::PhantomData;
+use std::mem;
+
+use snapshot_vec as sv;
+
+use super::{InPlace, NoError, Snapshot, UnificationStore, UnificationStoreBase};
+use super::{UnificationStoreMut, UnificationTable, UnifyIndex, UnifyKey, UnifyValue};
+
+/// A unification table whose unions carry justifications of type `J`.
+/// See the module documentation for details.
+#[derive(Clone, Debug)]
+pub struct ExplainedUnificationTable<S: UnificationStoreBase, J> {
+    table: UnificationTable<S>,
+    proofs: sv::SnapshotVec<ProofDelegate<S::Key, J>>,
+}
+
+/// An explained unification table that uses an "in-place" vector.
+#[allow(type_alias_bounds)]
+pub type InPlaceExplainedUnificationTable<K: UnifyKey, J> =
+    ExplainedUnificationTable<InPlace<K>, J>;
+
+/// Snapshot of an `ExplainedUnificationTable`. Like table snapshots,
+/// these must be committed or rolled back in LIFO order.
+pub struct ExplainedSnapshot<S: UnificationStore, J> {
+    table: Snapshot<S>,
+    proofs: sv::Snapshot,
+    marker: PhantomData<J>,
+}
+
+/// An edge in the proof forest, pointing from a key to its proof
+/// parent, along with the justification for the union that added it.
+#[derive(Clone, Debug)]
+struct ProofEdge<K, J> {
+    parent: K,
+    justification: J,
+}
+
+/// The proof edge of a key, if it is not the root of its proof tree,
+/// and the size of its class, if it is a root in the union-find table.
+#[derive(Clone, Debug)]
+struct ProofNode<K, J> {
+    edge: Option<ProofEdge<K, J>>,
+    class_size: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ProofDelegate<K, J>(PhantomData<(K, J)>);
+
+#[derive(Clone, Debug)]
+enum ProofUndo<K> {
+    /// A proof tree was re-rooted; the given key was its root before.
+    Rerooted(K),
+    /// An edge was added from the key at the given index.
+    Attached(usize),
+    /// The class size of the key at the given index was the given
+    /// size.
+    ClassSize(usize, usize),
+}
+
+impl<K: UnifyKey, J> sv::SnapshotVecDelegate for ProofDelegate<K, J> {
+    type Value = ProofNode<K, J>;
+    type Undo = ProofUndo<K>;
+
+    fn reverse(nodes: &mut Vec<ProofNode<K, J>>, undo: ProofUndo<K>) {
+        match undo {
+            ProofUndo::Rerooted(old_root) => {
+                reroot(nodes, old_root);
+            }
+            ProofUndo::Attached(index) => nodes[index].edge = None,
+            ProofUndo::ClassSize(index, size) => nodes[index].class_size = size,
+        }
+    }
+}
+
+/// Makes `key` the root of its proof tree by reversing the edges on
+/// the path from `key` to the current root, which is returned. The
+/// justifications are moved to the reversed edges.
+fn reroot<K: UnifyKey, J>(nodes: &mut [ProofNode<K, J>], key: K) -> K {
+    let mut reversed = None;
+    let mut current = key;
+    loop {
+        let index = current.index().as_usize();
+        match mem::replace(&mut nodes[index].edge, reversed) {
+            None => return current,
+            Some(edge) => {
+                reversed = Some(ProofEdge {
+                    parent: current,
+                    justification: edge.justification,
+                });
+                current = edge.parent;
+            }
+        }
+    }
+}
+
+// Manual impl avoids `Default` bound on `J`.
+impl<S: UnificationStoreBase + Default, J> Default for ExplainedUnificationTable<S, J> {
+    fn default() -> Self {
+        ExplainedUnificationTable {
+            table: UnificationTable::default(),
+            proofs: sv::SnapshotVec::new(),
+        }
+    }
+}
+
+impl<S: UnificationStoreBase + Default, J> ExplainedUnificationTable<S, J> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S: UnificationStoreBase, J> ExplainedUnificationTable<S, J> {
+    /// Returns the number of keys created so far.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Gives read-only access to the underlying unification table.
+    pub fn table(&self) -> &UnificationTable<S> {
+        &self.table
+    }
+}
+
+impl<S: UnificationStore, J> ExplainedUnificationTable<S, J> {
+    /// Starts a new snapshot. Each snapshot must be either
+    /// rolled back or committed in a "LIFO" (stack) order.
+    pub fn snapshot(&mut self) -> ExplainedSnapshot<S, J> {
+        ExplainedSnapshot {
+            table: self.table.snapshot(),
+            proofs: self.proofs.start_snapshot(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Reverses all unions (and their justifications) since the last
+    /// snapshot. Also removes any keys that have been created since
+    /// then.
+    pub fn rollback_to(&mut self, snapshot: ExplainedSnapshot<S, J>) {
+        self.proofs.rollback_to(snapshot.proofs);
+        self.table.rollback_to(snapshot.table);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: ExplainedSnapshot<S, J>) {
+        self.proofs.commit(snapshot.proofs);
+        self.table.commit(snapshot.table);
+    }
+}
+
+impl<S, K, V, J> ExplainedUnificationTable<S, J>
+where
+    S: UnificationStoreMut<Key = K, Value = V>,
+    K: UnifyKey<Value = V>,
+    V: UnifyValue,
+{
+    /// Creates a fresh key with the given value.
+    pub fn new_key(&mut self, value: V) -> K {
+        let key = self.table.new_key(value);
+        self.proofs.push(ProofNode {
+            edge: None,
+            class_size: 1,
+        });
+        key
+    }
+
+    /// Given a key, returns the (current) root key.
+    pub fn find<K1: Into<K>>(&mut self, id: K1) -> K {
+        self.table.find(id)
+    }
+
+    /// Given two keys, indicates whether they have been unioned together.
+    pub fn unioned<K1: Into<K>, K2: Into<K>>(&mut self, a_id: K1, b_id: K2) -> bool {
+        self.table.unioned(a_id, b_id)
+    }
+
+    /// Returns the current value for the given key.
+    pub fn probe_value<K1: Into<K>>(&mut self, id: K1) -> V {
+        self.table.probe_value(id)
+    }
+
+    /// Unions two keys without the possibility of failure, recording
+    /// `justification` as the reason; only applicable when unify
+    /// values use `NoError` as their error type.
+    pub fn union<K1, K2>(&mut self, a_id: K1, b_id: K2, justification: J)
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+        V: UnifyValue<Error = NoError>,
+    {
+        self.unify_var_var(a_id, b_id, justification).unwrap();
+    }
+
+    /// Unions together two variables, merging their values and
+    /// recording `justification` as the reason. If merging the values
+    /// fails, the error is propagated and this method has no effect.
+    ///
+    /// If the two keys are already unioned, the justification is
+    /// discarded: the existing explanation still holds.
+    pub fn unify_var_var<K1, K2>(
+        &mut self,
+        a_id: K1,
+        b_id: K2,
+        justification: J,
+    ) -> Result<(), V::Error>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        let a_id = a_id.into();
+        let b_id = b_id.into();
+
+        let root_a = self.table.find(a_id);
+        let root_b = self.table.find(b_id);
+        if root_a == root_b {
+            return Ok(());
+        }
+
+        self.table.unify_var_var(a_id, b_id)?;
+        let size_a = self.class_size(root_a);
+        let size_b = self.class_size(root_b);
+        let new_root = self.table.find(a_id);
+        self.set_class_size(new_root, size_a + size_b);
+
+        // Re-root the proof tree of the smaller class, so that each key
+        // is on a re-rooted path at most O(log n) times.
+        if size_a <= size_b {
+            self.add_proof_edge(a_id, b_id, justification);
+        } else {
+            self.add_proof_edge(b_id, a_id, justification);
+        }
+        Ok(())
+    }
+
+    /// Sets the value of the key `a_id` to `b`, attempting to merge
+    /// with the previous value. Values are not explained.
+    pub fn unify_var_value<K1: Into<K>>(&mut self, a_id: K1, b: V) -> Result<(), V::Error> {
+        self.table.unify_var_value(a_id, b)
+    }
+
+    /// Returns the justifications that link `a_id` to `b_id`, in order
+    /// from `a_id` to `b_id`, or `None` if the two keys are not
+    /// unioned. Each justification appears at most once, and every one
+    /// of them is needed: this is the unique path between the two keys
+    /// in the proof forest. Explaining a key with itself yields an
+    /// empty chain.
+    pub fn explain<'a, K1, K2>(&'a self, a_id: K1, b_id: K2) -> Option<Vec<&'a J>>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+        K: 'a,
+    {
+        let mut a = a_id.into();
+        let mut b = b_id.into();
+        if !self.table.read_unioned(a, b) {
+            return None;
+        }
+
+        let mut a_depth = self.proof_depth(a);
+        let mut b_depth = self.proof_depth(b);
+        let mut from_a = vec![];
+        let mut from_b = vec![];
+
+        // Walk up from the deeper key until both are at the same
+        // depth, then walk up from both until they meet.
+        while a_depth > b_depth {
+            let (parent, justification) = self.proof_edge(a).unwrap();
+            from_a.push(justification);
+            a = parent;
+            a_depth -= 1;
+        }
+        while b_depth > a_depth {
+            let (parent, justification) = self.proof_edge(b).unwrap();
+            from_b.push(justification);
+            b = parent;
+            b_depth -= 1;
+        }
+        while a != b {
+            let (a_parent, a_justification) = self.proof_edge(a).unwrap();
+            let (b_parent, b_justification) = self.proof_edge(b).unwrap();
+            from_a.push(a_justification);
+            from_b.push(b_justification);
+            a = a_parent;
+            b = b_parent;
+        }
+
+        from_a.extend(from_b.into_iter().rev());
+        Some(from_a)
+    }
+
+    /// Returns the proof parent of `key` and the justification of the
+    /// edge leading to it, or `None` if `key` is a proof root.
+    fn proof_edge<'a>(&'a self, key: K) -> Option<(K, &'a J)>
+    where
+        K: 'a,
+    {
+        self.proofs[key.index().as_usize()]
+            .edge
+            .as_ref()
+            .map(|edge| (edge.parent, &edge.justification))
+    }
+
+    fn proof_depth<'a>(&'a self, mut key: K) -> usize
+    where
+        K: 'a,
+    {
+        let mut depth = 0;
+        while let Some((parent, _)) = self.proof_edge(key) {
+            key = parent;
+            depth += 1;
+        }
+        depth
+    }
+
+    fn class_size(&self, root: K) -> usize {
+        self.proofs[root.index().as_usize()].class_size
+    }
+
+    fn set_class_size(&mut self, root: K, size: usize) {
+        let index = root.index().as_usize();
+        self.proofs.update_with_undo(index, |node| {
+            ProofUndo::ClassSize(index, mem::replace(&mut node.class_size, size))
+        });
+    }
+
+    /// Makes `a` the root of its proof tree, then adds an edge from `a`
+    /// to `b`. Neither step clones a justification: the undo log only
+    /// records how to reverse them.
+    fn add_proof_edge(&mut self, a: K, b: K, justification: J) {
+        debug!("add_proof_edge(a={:?}, b={:?})", a, b);
+
+        let old_root = reroot(&mut self.proofs, a);
+        self.proofs.record(ProofUndo::Rerooted(old_root));
+
+        let index = a.index().as_usize();
+        self.proofs.update_with_undo(index, |node| {
+            node.edge = Some(ProofEdge {
+                parent: b,
+                justification,
+            });
+            ProofUndo::Attached(index)
+        });
+    }
+}
@@ -0,0 +1,286 @@
+//! A compact, versioned binary format for unification tables, and a
+//! read-only view that works directly on the encoded bytes.
+//!
+//! `UnificationTable::write_frozen` writes the current state of a table
+//! (which must not have any open snapshots). `FrozenUnificationTable`
+//! then answers `read_find`, `read_unioned` and `read_probe_value` by
+//! looking at the bytes in place, so the encoded table can be memory
+//! mapped and queried without deserializing it first.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+
+use super::{
+    key_from_usize, Strategy, UnificationStoreMut, UnificationTable, UnifyIndex, UnifyKey,
+    RETIRED_RANK,
+};
+
+/// The magic bytes at the start of every frozen table.
+pub const FROZEN_MAGIC: [u8; 4] = *b"ENAU";
+
+/// The version of the layout written by `write_frozen`.
+pub const FROZEN_VERSION: u32 = 1;
+
+const HEADER_LEN: usize = 16;
+
+/// Encodes values of type `V` into a fixed number of bytes, so that the
+/// value of any key can be found without decoding the whole table.
+pub trait ValueCodec<V> {
+    /// The number of bytes taken by every encoded value.
+    const WIDTH: usize;
+
+    /// Writes `value` into `bytes`, which is exactly `WIDTH` bytes long.
+    fn encode(value: &V, bytes: &mut [u8]);
+
+    /// Reads a value back from `bytes`, which is exactly `WIDTH` bytes
+    /// long and was filled in by `encode`.
+    fn decode(bytes: &[u8]) -> V;
+}
+
+/// Codec for tables without values: the values take no space at all.
+#[derive(Copy, Clone, Debug)]
+pub struct UnitCodec;
+
+impl ValueCodec<()> for UnitCodec {
+    const WIDTH: usize = 0;
+
+    fn encode(_: &(), _: &mut [u8]) {}
+
+    fn decode(_: &[u8]) {}
+}
+
+/// Reasons why a byte slice is not a valid frozen table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FrozenError {
+    /// The data does not start with `FROZEN_MAGIC`.
+    BadMagic,
+    /// The data was written with a version of the layout that we do
+    /// not understand.
+    UnsupportedVersion(u32),
+    /// The data was written with a codec of a different width.
+    ValueWidth { expected: usize, found: usize },
+    /// The length of the data does not match the header.
+    BadLength { expected: usize, found: usize },
+    /// The key with the given index has an invalid parent.
+    BadParent(u32),
+    /// The table has more keys than the index type of the key can
+    /// represent.
+    TooManyKeys(u32),
+}
+
+impl fmt::Display for FrozenError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            FrozenError::BadMagic => write!(fmt, "not a frozen unification table"),
+            FrozenError::UnsupportedVersion(version) => {
+                write!(fmt, "unsupported frozen table version {}", version)
+            }
+            FrozenError::ValueWidth { expected, found } => write!(
+                fmt,
+                "values are {} bytes wide, but the codec expects {}",
+                found, expected
+            ),
+            FrozenError::BadLength { expected, found } => {
+                write!(fmt, "expected {} bytes of data, found {}", expected, found)
+            }
+            FrozenError::BadParent(index) => write!(fmt, "key {} has an invalid parent", index),
+            FrozenError::TooManyKeys(len) => {
+                write!(fmt, "{} keys do not fit in the index type of the key", len)
+            }
+        }
+    }
+}
+
+impl Error for FrozenError {}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+impl<S: UnificationStoreMut, St: Strategy> UnificationTable<S, St> {
+    /// Writes the table in the frozen format described in the
+    /// `FrozenUnificationTable` documentation, encoding values with
+    /// `C`. Fails with `InvalidInput` if a snapshot is open, since the
+    /// changes made in it might still be rolled back, if some keys have
+    /// been retired, since the format cannot tell them from live ones,
+    /// or if the table has more keys than fit in the `u32` indices of
+    /// the format.
+    pub fn write_frozen<C, W>(&self, mut out: W) -> io::Result<()>
+    where
+        C: ValueCodec<S::Value>,
+        W: io::Write,
+    {
+        if self.values.in_snapshot() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot freeze a unification table while snapshots are open",
+            ));
+        }
+
+        if (0..self.len()).any(|index| self.values.rank(index) == RETIRED_RANK) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot freeze a unification table with retired keys",
+            ));
+        }
+
+        if self.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many keys for the frozen format",
+            ));
+        }
+
+        out.write_all(&FROZEN_MAGIC)?;
+        out.write_all(&FROZEN_VERSION.to_le_bytes())?;
+        out.write_all(&(self.len() as u32).to_le_bytes())?;
+        out.write_all(&(C::WIDTH as u32).to_le_bytes())?;
+
+        let mut value_bytes = vec![0; C::WIDTH];
+        for index in 0..self.len() {
+            let parent = self.values.parent(index).index().as_usize() as u32;
+            C::encode(self.values.value(index), &mut value_bytes);
+            out.write_all(&parent.to_le_bytes())?;
+            out.write_all(&self.values.rank(index).to_le_bytes())?;
+            out.write_all(&value_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// A read-only unification table backed by a byte slice in the frozen
+/// format written by `UnificationTable::write_frozen`.
+///
+/// # Layout
+///
+/// All integers are little-endian `u32`s. The file starts with a
+/// 16-byte header:
+///
+/// | offset | contents                                  |
+/// |--------|-------------------------------------------|
+/// | 0      | the magic bytes `ENAU`                    |
+/// | 4      | the format version, currently `1`         |
+/// | 8      | the number of keys, `len`                 |
+/// | 12     | the width of an encoded value, in bytes   |
+///
+/// followed by `len` records, one per key in index order, each made of
+/// the key's parent index, its rank, and its value as encoded by a
+/// `ValueCodec`. Values are only meaningful for root keys. Paths are
+/// stored as they are in the table, without further compression.
+///
+/// The data is validated when a `FrozenUnificationTable` is created:
+/// every parent must be in bounds and every non-root key must have a
+/// strictly smaller rank than its parent, which guarantees that
+/// `read_find` terminates.
+pub struct FrozenUnificationTable<'a, K, C> {
+    bytes: &'a [u8],
+    len: usize,
+    marker: PhantomData<(K, C)>,
+}
+
+impl<'a, K, C> FrozenUnificationTable<'a, K, C>
+where
+    K: UnifyKey,
+    C: ValueCodec<K::Value>,
+{
+    /// Checks that `bytes` holds a valid frozen table with values
+    /// encoded by `C`, and wraps it.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, FrozenError> {
+        if bytes.len() < HEADER_LEN || bytes[..4] != FROZEN_MAGIC {
+            return Err(FrozenError::BadMagic);
+        }
+        let version = read_u32(bytes, 4);
+        if version != FROZEN_VERSION {
+            return Err(FrozenError::UnsupportedVersion(version));
+        }
+        let len = read_u32(bytes, 8) as usize;
+        if len > 0 && K::Index::try_from_usize(len - 1).is_none() {
+            return Err(FrozenError::TooManyKeys(len as u32));
+        }
+        let width = read_u32(bytes, 12) as usize;
+        if width != C::WIDTH {
+            return Err(FrozenError::ValueWidth {
+                expected: C::WIDTH,
+                found: width,
+            });
+        }
+        let expected = len
+            .checked_mul(Self::record_len())
+            .and_then(|records| records.checked_add(HEADER_LEN));
+        if expected != Some(bytes.len()) {
+            return Err(FrozenError::BadLength {
+                expected: expected.unwrap_or(usize::MAX),
+                found: bytes.len(),
+            });
+        }
+
+        let table = FrozenUnificationTable {
+            bytes,
+            len,
+            marker: PhantomData,
+        };
+        for index in 0..len {
+            let parent = table.parent(index);
+            if parent >= len || (parent != index && table.rank(parent) <= table.rank(index)) {
+                return Err(FrozenError::BadParent(index as u32));
+            }
+        }
+        Ok(table)
+    }
+
+    fn record_len() -> usize {
+        8 + C::WIDTH
+    }
+
+    fn record(&self, index: usize) -> &'a [u8] {
+        assert!(index < self.len, "key {} out of bounds", index);
+        let start = HEADER_LEN + index * Self::record_len();
+        &self.bytes[start..start + Self::record_len()]
+    }
+
+    fn parent(&self, index: usize) -> usize {
+        read_u32(self.record(index), 0) as usize
+    }
+
+    fn rank(&self, index: usize) -> u32 {
+        read_u32(self.record(index), 4)
+    }
+
+    /// Returns the number of keys in the table.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the table has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Given a key, returns its root key.
+    pub fn read_find<K1: Into<K>>(&self, id: K1) -> K {
+        let mut index = id.into().index().as_usize();
+        loop {
+            let parent = self.parent(index);
+            if parent == index {
+                return key_from_usize(index);
+            }
+            index = parent;
+        }
+    }
+
+    /// Given two keys, indicates whether they have been unioned together.
+    pub fn read_unioned<K1: Into<K>, K2: Into<K>>(&self, a_id: K1, b_id: K2) -> bool {
+        self.read_find(a_id) == self.read_find(b_id)
+    }
+
+    /// Returns the current value for the given key, decoding it from
+    /// the record of its root.
+    pub fn read_probe_value<K1: Into<K>>(&self, id: K1) -> K::Value {
+        let root = self.read_find(id);
+        C::decode(&self.record(root.index().as_usize())[8..])
+    }
+}
@@ -31,21 +31,52 @@
 //! The best way to see how it is used is to read the `tests.rs` file;
 //! search for e.g. `UnitKey`.
 
-use std::fmt::Debug;
+use std::error::Error;
+use std::fmt::{self, Debug};
 use std::marker;
-use std::ops::Range;
+use std::ops::{self, Range};
 
 use snapshot_vec::{self as sv, UndoLog};
-use undo_log::{UndoLogs, VecLog};
+use undo_log::{SnapshotError, UndoLogs, VecLog};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 mod backing_vec;
+#[cfg(feature = "persistent")]
+mod persistent_vec;
 pub use self::backing_vec::{
-    Delegate, InPlace, UnificationStore, UnificationStoreBase, UnificationStoreMut,
+    Delegate, InPlace, Packed, UnificationStore, UnificationStoreBase, UnificationStoreMut,
+    VarValueUndo,
 };
 
 #[cfg(feature = "persistent")]
 pub use self::backing_vec::Persistent;
 
+mod explain;
+pub use self::explain::{
+    ExplainedSnapshot, ExplainedUnificationTable, InPlaceExplainedUnificationTable,
+};
+
+mod weighted;
+pub use self::weighted::{Group, WeightedSnapshot, WeightedUnificationTable};
+
+mod parity;
+pub use self::parity::{ParityRelation, ParitySnapshot, ParityUnificationTable};
+
+mod strategy;
+pub use self::strategy::{
+    ByRank, BySize, DefaultStrategy, FindStrategy, FullCompression, NoCompression, PathHalving,
+    PathSplitting, Strategy, UnionStrategy,
+};
+
+mod concurrent;
+pub use self::concurrent::ConcurrentUnificationTable;
+
+mod frozen;
+pub use self::frozen::{FrozenError, FrozenUnificationTable, UnitCodec, ValueCodec};
+pub use self::frozen::{FROZEN_MAGIC, FROZEN_VERSION};
+
 #[cfg(test)]
 mod tests;
 
@@ -58,14 +89,22 @@ mod tests;
 /// `IntVid`, this is `Option<IntVarValue>`, representing some
 /// (possibly not yet known) sort of integer.
 ///
+/// Each key type also chooses the integer type of its index, which
+/// bounds the number of keys a table can hold. Creating more keys than
+/// the index type can represent panics in `new_key`, or fails in
+/// `try_new_key`, rather than wrapping around.
+///
 /// Clients are expected to provide implementations of this trait; you
 /// can see some examples in the `test` module.
 pub trait UnifyKey: Copy + Clone + Debug + PartialEq {
     type Value: UnifyValue;
 
-    fn index(&self) -> u32;
+    /// The index type; one of `u16`, `u32`, `u64` or `usize`.
+    type Index: UnifyIndex;
+
+    fn index(&self) -> Self::Index;
 
-    fn from_index(u: u32) -> Self;
+    fn from_index(u: Self::Index) -> Self;
 
     fn tag() -> &'static str;
 
@@ -151,6 +190,65 @@ pub struct NoError {
     _dummy: (),
 }
 
+/// The integer types that can serve as the index of a `UnifyKey`.
+pub trait UnifyIndex: Copy + Debug + PartialEq {
+    /// Converts `index`, or returns `None` if it does not fit.
+    fn try_from_usize(index: usize) -> Option<Self>;
+
+    fn as_usize(self) -> usize;
+}
+
+macro_rules! impl_unify_index {
+    ($($t:ty),*) => {
+        $(
+            impl UnifyIndex for $t {
+                #[inline]
+                fn try_from_usize(index: usize) -> Option<$t> {
+                    if index as u64 <= <$t>::MAX as u64 {
+                        Some(index as $t)
+                    } else {
+                        None
+                    }
+                }
+
+                #[inline]
+                fn as_usize(self) -> usize {
+                    self as usize
+                }
+            }
+        )*
+    };
+}
+
+impl_unify_index!(u16, u32, u64, usize);
+
+/// Converts `index` to a key, panicking if it does not fit in the index
+/// type of `K`.
+#[inline]
+pub(crate) fn key_from_usize<K: UnifyKey>(index: usize) -> K {
+    match K::Index::try_from_usize(index) {
+        Some(index) => K::from_index(index),
+        None => panic!(
+            "{}: key index {} overflows the index type of the key",
+            K::tag(),
+            index
+        ),
+    }
+}
+
+/// Error returned by `UnificationTable::try_new_key` when the table
+/// already holds as many keys as the index type of the key allows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyOverflowError;
+
+impl fmt::Display for KeyOverflowError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "too many keys for the index type of the key")
+    }
+}
+
+impl Error for KeyOverflowError {}
+
 /// Value of a unification key. We implement Tarjan's union-find
 /// algorithm: when two keys are unified, one of them is converted
 /// into a "redirect" pointing at the other. These redirects form a
@@ -160,10 +258,20 @@ pub struct NoError {
 /// time of the algorithm under control. For more information, see
 /// <http://en.wikipedia.org/wiki/Disjoint-set_data_structure>.
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "K: Serialize, K::Value: Serialize",
+        deserialize = "K: Deserialize<'de>, K::Value: Deserialize<'de>"
+    ))
+)]
 pub struct VarValue<K: UnifyKey> {
     parent: K,       // if equal to self, this is a root
     value: K::Value, // value assigned (only relevant to root)
     rank: u32,       // max depth (only relevant to root)
+    #[cfg(feature = "class-members")]
+    next: K, // next member of the class, in a circular list
 }
 
 /// Table of unification keys and their values. You must define a key type K
@@ -174,15 +282,55 @@ pub struct VarValue<K: UnifyKey> {
 ///     in place.
 ///   - To do backtracking, you can employ the `snapshot` and `rollback_to`
 ///     methods.
+///   - With the `serde` feature, the table can be serialized, as long as
+///     no snapshot is open.
+/// - packed (`UnificationTable<Packed<K>>` or `PackedUnificationTable<K>`):
+///   - Like in-place mode, but the parents and ranks are kept in one
+///     dense array and the values in another, so `find` does not read
+///     the values. This pays off when values are large.
 /// - persistent (`UnificationTable<Persistent<K>>` or `PersistentUnificationTable<K>`):
 ///   - In this mode, we use a persistent vector to store the data, so that
 ///     cloning the table is an O(1) operation.
-///   - This implies that ordinary operations are quite a bit slower though.
+///   - Parts of the vector that are not shared with a snapshot are
+///     updated in place, but ordinary operations are still somewhat
+///     slower than in-place mode, and path compression is deferred
+///     while a snapshot is open.
 ///   - Requires the `persistent` feature be selected in your Cargo.toml file.
-#[derive(Clone, Debug, Default)]
-pub struct UnificationTable<S: UnificationStoreBase> {
+///
+/// In either mode, the optional strategy parameter `St` selects how
+/// roots are chosen by a union and how `find` compresses paths; see
+/// the `Strategy` trait.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "S: Serialize, S::Key: Serialize",
+        deserialize = "S: Deserialize<'de>, S::Key: Deserialize<'de>"
+    ))
+)]
+pub struct UnificationTable<S: UnificationStoreBase, St: Strategy = DefaultStrategy> {
     /// Indicates the current value of each key.
     values: S,
+
+    /// Keys that have been retired and whose slots may be reused by
+    /// `new_key`. This is only a hint: rolling back a snapshot may
+    /// revive a retired key, so entries are checked before reuse.
+    retired: Vec<S::Key>,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    strategy: marker::PhantomData<St>,
+}
+
+// Manual impl avoids `Default` bound on `S::Key` and `St`.
+impl<S: UnificationStoreBase + Default, St: Strategy> Default for UnificationTable<S, St> {
+    fn default() -> Self {
+        UnificationTable {
+            values: S::default(),
+            retired: Vec::new(),
+            strategy: marker::PhantomData,
+        }
+    }
 }
 
 pub type UnificationStorage<K> = Vec<VarValue<K>>;
@@ -196,6 +344,10 @@ pub type InPlaceUnificationTable<
     L = VecLog<UndoLog<Delegate<K>>>,
 > = UnificationTable<InPlace<K, V, L>>;
 
+/// A unification table that keeps its parents and values in separate
+/// arrays.
+pub type PackedUnificationTable<K> = UnificationTable<Packed<K>>;
+
 /// A unification table that uses a "persistent" vector.
 #[cfg(feature = "persistent")]
 #[allow(type_alias_bounds)]
@@ -209,16 +361,19 @@ pub struct Snapshot<S: UnificationStore> {
     snapshot: S::Snapshot,
 }
 
-impl<K: UnifyKey> VarValue<K> {
-    fn new_var(key: K, value: K::Value) -> VarValue<K> {
-        VarValue::new(key, value, 0)
-    }
+/// Rank given to retired keys. Union strategies keep the ranks of live
+/// roots below it: under `ByRank` they are bounded by the log of the
+/// number of keys, and under `BySize` they saturate just below it.
+const RETIRED_RANK: u32 = u32::MAX;
 
+impl<K: UnifyKey> VarValue<K> {
     fn new(parent: K, value: K::Value, rank: u32) -> VarValue<K> {
         VarValue {
             parent: parent, // this is a root
             value: value,
             rank: rank,
+            #[cfg(feature = "class-members")]
+            next: parent, // a class of one
         }
     }
 
@@ -231,15 +386,12 @@ impl<K: UnifyKey> VarValue<K> {
         self.value = value;
     }
 
-    fn parent(&self, self_key: K) -> Option<K> {
-        self.if_not_self(self.parent, self_key)
-    }
-
-    fn if_not_self(&self, key: K, self_key: K) -> Option<K> {
-        if key == self_key {
-            None
-        } else {
-            Some(key)
+    fn retire(&mut self, self_key: K) {
+        self.parent = self_key;
+        self.rank = RETIRED_RANK;
+        #[cfg(feature = "class-members")]
+        {
+            self.next = self_key;
         }
     }
 }
@@ -249,6 +401,10 @@ where
 {
     /// Creates a `UnificationTable` using an external `undo_log`, allowing mutating methods to be
     /// called if `L` does not implement `UndoLogs`
+    ///
+    /// The returned table does not outlive the borrow, so it never reuses the slots of keys
+    /// retired earlier, and slots of keys retired through it are not reused by later calls to
+    /// `new_key`.
     pub fn with_log<'a, L>(
         &'a mut self,
         undo_log: L,
@@ -260,6 +416,8 @@ where
             values: InPlace {
                 values: self.values.values.with_log(undo_log),
             },
+            retired: Vec::new(),
+            strategy: marker::PhantomData,
         }
     }
 }
@@ -269,13 +427,13 @@ where
 // other type parameter U, and we have no way to say
 // Option<U>:LatticeValue.
 
-impl<S: UnificationStoreBase + Default> UnificationTable<S> {
+impl<S: UnificationStoreBase + Default, St: Strategy> UnificationTable<S, St> {
     pub fn new() -> Self {
         Self::default()
     }
 }
 
-impl<S: UnificationStore> UnificationTable<S> {
+impl<S: UnificationStore, St: Strategy> UnificationTable<S, St> {
     /// Starts a new snapshot. Each snapshot must be either
     /// rolled back or committed in a "LIFO" (stack) order.
     pub fn snapshot(&mut self) -> Snapshot<S> {
@@ -299,29 +457,238 @@ impl<S: UnificationStore> UnificationTable<S> {
         self.values.commit(snapshot.snapshot);
     }
 
+    /// Like `rollback_to`, but returns an error instead of panicking if
+    /// `snapshot` is not the innermost open snapshot of this table. The
+    /// error hands the snapshot back, so that it can still be closed
+    /// later.
+    pub fn try_rollback_to(
+        &mut self,
+        snapshot: Snapshot<S>,
+    ) -> Result<(), (Snapshot<S>, SnapshotError)> {
+        debug!("{}: try_rollback_to()", S::tag());
+        self.values
+            .try_rollback_to(snapshot.snapshot)
+            .map_err(|(snapshot, err)| {
+                let marker = marker::PhantomData;
+                (Snapshot { marker, snapshot }, err)
+            })
+    }
+
+    /// Like `commit`, but returns an error instead of panicking if
+    /// `snapshot` is not the innermost open snapshot of this table. The
+    /// error hands the snapshot back, as with `try_rollback_to`.
+    pub fn try_commit(
+        &mut self,
+        snapshot: Snapshot<S>,
+    ) -> Result<(), (Snapshot<S>, SnapshotError)> {
+        debug!("{}: try_commit()", S::tag());
+        self.values
+            .try_commit(snapshot.snapshot)
+            .map_err(|(snapshot, err)| {
+                let marker = marker::PhantomData;
+                (Snapshot { marker, snapshot }, err)
+            })
+    }
+
     /// Returns the keys of all variables created since the `snapshot`.
     pub fn vars_since_snapshot(&self, snapshot: &Snapshot<S>) -> Range<S::Key> {
         let range = self.values.values_since_snapshot(&snapshot.snapshot);
-        S::Key::from_index(range.start as u32)..S::Key::from_index(range.end as u32)
+        key_from_usize(range.start)..key_from_usize(range.end)
+    }
+
+    /// Runs `f` inside a snapshot that is always rolled back, so
+    /// whatever it does to the table is undone, and returns its result.
+    pub fn probe<R, F>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let snapshot = self.snapshot();
+        let result = f(self);
+        self.rollback_to(snapshot);
+        result
+    }
+
+    /// Runs `f` inside a snapshot, which is committed if `f` returns
+    /// `Ok` and rolled back if it returns `Err`.
+    pub fn commit_if_ok<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Self) -> Result<T, E>,
+    {
+        let snapshot = self.snapshot();
+        let result = f(self);
+        if result.is_ok() {
+            self.commit(snapshot);
+        } else {
+            self.rollback_to(snapshot);
+        }
+        result
+    }
+
+    /// Runs `f` inside a snapshot that is always committed. This only
+    /// matters when a snapshot further out is rolled back, in which
+    /// case the changes made by `f` are undone as a unit.
+    pub fn commit_unconditionally<R, F>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let snapshot = self.snapshot();
+        let result = f(self);
+        self.commit(snapshot);
+        result
+    }
+
+    /// Starts a new snapshot and returns a guard that derefs to the
+    /// table. The snapshot is rolled back when the guard is dropped,
+    /// unless `SnapshotGuard::commit` is called first, so an early
+    /// return or a panic cannot leave it open.
+    pub fn snapshot_guard(&mut self) -> SnapshotGuard<'_, S, St> {
+        let snapshot = self.snapshot();
+        SnapshotGuard {
+            table: self,
+            snapshot: Some(snapshot),
+        }
+    }
+}
+
+/// A snapshot of a `UnificationTable` that is rolled back when dropped.
+/// See `UnificationTable::snapshot_guard`.
+pub struct SnapshotGuard<'a, S: UnificationStore, St: Strategy = DefaultStrategy> {
+    table: &'a mut UnificationTable<S, St>,
+    // Only `None` once the snapshot has been committed or rolled back.
+    snapshot: Option<Snapshot<S>>,
+}
+
+impl<'a, S: UnificationStore, St: Strategy> SnapshotGuard<'a, S, St> {
+    /// Commits all changes made since the guard was created.
+    pub fn commit(mut self) {
+        let snapshot = self.snapshot.take().unwrap();
+        self.table.commit(snapshot);
+    }
+
+    /// Rolls back all changes made since the guard was created. This is
+    /// what dropping the guard does; it is only more explicit.
+    pub fn rollback(mut self) {
+        let snapshot = self.snapshot.take().unwrap();
+        self.table.rollback_to(snapshot);
+    }
+
+    /// Returns the keys of all variables created since the guard was
+    /// created.
+    pub fn vars_since_snapshot(&self) -> Range<S::Key> {
+        self.table
+            .vars_since_snapshot(self.snapshot.as_ref().unwrap())
+    }
+}
+
+impl<'a, S: UnificationStore, St: Strategy> ops::Deref for SnapshotGuard<'a, S, St> {
+    type Target = UnificationTable<S, St>;
+    fn deref(&self) -> &UnificationTable<S, St> {
+        self.table
+    }
+}
+
+impl<'a, S: UnificationStore, St: Strategy> ops::DerefMut for SnapshotGuard<'a, S, St> {
+    fn deref_mut(&mut self) -> &mut UnificationTable<S, St> {
+        self.table
+    }
+}
+
+impl<'a, S: UnificationStore, St: Strategy> Drop for SnapshotGuard<'a, S, St> {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            self.table.rollback_to(snapshot);
+        }
     }
 }
 
-impl<S: UnificationStoreBase> UnificationTable<S> {
+impl<S: UnificationStoreBase, St: Strategy> UnificationTable<S, St> {
     /// Returns the number of keys created so far.
     pub fn len(&self) -> usize {
         self.values.len()
     }
+
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
 }
 
-impl<S: UnificationStoreMut> UnificationTable<S> {
+impl<S: UnificationStoreMut, St: Strategy> UnificationTable<S, St> {
     /// Starts a new snapshot. Each snapshot must be either
-    /// Creates a fresh key with the given value.
+    /// Creates a fresh key with the given value. Outside of a
+    /// snapshot, this reuses the slot of a retired key if there is one.
+    ///
+    /// Panics if the table already holds as many keys as the index type
+    /// of the key can represent; see `try_new_key`.
     pub fn new_key(&mut self, value: S::Value) -> S::Key {
+        match self.try_new_key(value) {
+            Ok(key) => key,
+            Err(error) => panic!("{}: {}", S::tag(), error),
+        }
+    }
+
+    /// Like `new_key`, but returns an error instead of panicking if the
+    /// index of the new key would not fit in the index type of the key.
+    pub fn try_new_key(&mut self, value: S::Value) -> Result<S::Key, KeyOverflowError> {
+        if let Some(key) = self.pop_retired_key() {
+            self.values.update(key.index().as_usize(), |slot| {
+                *slot = VarValue::new(key, value, St::Union::initial_rank());
+            });
+            debug!("{}: reused retired key: {:?}", S::tag(), key);
+            return Ok(key);
+        }
+
         let len = self.values.len();
-        let key: S::Key = UnifyKey::from_index(len as u32);
-        self.values.push(VarValue::new_var(key, value));
+        let index = match <S::Key as UnifyKey>::Index::try_from_usize(len) {
+            Some(index) => index,
+            None => return Err(KeyOverflowError),
+        };
+        let key: S::Key = UnifyKey::from_index(index);
+        self.values
+            .push(VarValue::new(key, value, St::Union::initial_rank()));
         debug!("{}: created new key: {:?}", S::tag(), key);
-        key
+        Ok(key)
+    }
+
+    /// Creates `len` fresh keys at once, giving each the value returned
+    /// by `value`, and returns their range. Unlike calling `new_key` in
+    /// a loop, this records a single undo entry for all of the keys. It
+    /// never reuses the slots of retired keys, so the keys are always
+    /// consecutive.
+    ///
+    /// Panics if the end of the range does not fit in the index type of
+    /// the key.
+    pub fn new_keys(
+        &mut self,
+        len: usize,
+        mut value: impl FnMut(S::Key) -> S::Value,
+    ) -> Range<S::Key> {
+        let start = self.values.len();
+        let end: S::Key = key_from_usize(start + len);
+        let rank = St::Union::initial_rank();
+        self.values.push_all((start..start + len).map(|index| {
+            let key = key_from_usize(index);
+            VarValue::new(key, value(key), rank)
+        }));
+
+        let range = key_from_usize(start)..end;
+        debug!("{}: created new keys: {:?}", S::tag(), range);
+        range
+    }
+
+    fn pop_retired_key(&mut self) -> Option<S::Key> {
+        // Keys created during a snapshot must come after all older
+        // keys, or `vars_since_snapshot` would miss them, so we only
+        // reuse slots when no snapshot is open.
+        if self.values.in_snapshot() {
+            return None;
+        }
+        while let Some(key) = self.retired.pop() {
+            if key.index().as_usize() < self.values.len() && self.is_retired(key) {
+                return Some(key);
+            }
+        }
+        None
     }
 
     /// Reserve memory for `num_new_keys` to be created. Does not
@@ -332,23 +699,57 @@ impl<S: UnificationStoreMut> UnificationTable<S> {
 
     /// Clears all unifications that have been performed, resetting to
     /// the initial state. The values of each variable are given by
-    /// the closure.
+    /// the closure. Retired keys become ordinary keys again.
     pub fn reset_unifications(&mut self, mut value: impl FnMut(S::Key) -> S::Value) {
+        self.retired.clear();
         self.values.reset_unifications(|i| {
-            let key = UnifyKey::from_index(i as u32);
+            let key = key_from_usize(i);
             let value = value(key);
-            VarValue::new_var(key, value)
+            VarValue::new(key, value, St::Union::initial_rank())
         });
     }
 
+    /// Obtains the parent of a particular key, or `None` if it is a
+    /// root.
+    fn parent(&self, key: S::Key) -> Option<S::Key> {
+        let parent = self.values.parent(key.index().as_usize());
+        if parent == key {
+            None
+        } else {
+            Some(parent)
+        }
+    }
+
+    fn rank(&self, key: S::Key) -> u32 {
+        self.values.rank(key.index().as_usize())
+    }
+
     /// Obtains the current value for a particular key.
     /// Not for end-users; they can use `probe_value`.
-    fn value(&self, key: S::Key) -> &VarValue<S::Key> {
-        &self.values[key.index() as usize]
+    fn value(&self, key: S::Key) -> &S::Value {
+        self.values.value(key.index().as_usize())
+    }
+
+    fn is_retired(&self, key: S::Key) -> bool {
+        self.rank(key) == RETIRED_RANK
+    }
+
+    /// Panics if `root` has been retired. A retired key is a root of
+    /// its own that no other key points at, so checking the root found
+    /// for a key is enough to catch uses of retired keys.
+    #[inline]
+    fn assert_live(&self, root: S::Key) {
+        assert!(
+            !self.is_retired(root),
+            "{}: key {:?} is retired",
+            S::tag(),
+            root
+        );
     }
 
     /// Find the root node for `vid`. This uses the standard
-    /// union-find algorithm with path compression:
+    /// union-find algorithm, compressing the path as chosen by the
+    /// `FindStrategy` of the table:
     /// <http://en.wikipedia.org/wiki/Disjoint-set_data_structure>.
     ///
     /// NB. This is a building-block operation and you would probably
@@ -358,27 +759,18 @@ impl<S: UnificationStoreMut> UnificationTable<S> {
     /// callsites. `uninlined_get_root_key` is the never-inlined version.
     #[inline(always)]
     fn inlined_get_root_key(&mut self, vid: S::Key) -> S::Key {
-        let redirect = {
-            match self.value(vid).parent(vid) {
-                None => return vid,
-                Some(redirect) => redirect,
-            }
-        };
-
-        let root_key: S::Key = self.uninlined_get_root_key(redirect);
-        if root_key != redirect {
-            // Path compression
-            self.update_value(vid, |value| value.parent = root_key);
+        if self.parent(vid).is_none() {
+            return vid;
         }
 
-        root_key
+        self.uninlined_get_root_key(vid)
     }
 
     /// read-only version of `inlined_get_root_key`
     #[inline(always)]
     fn read_inlined_get_root_key(&self, vid: S::Key) -> S::Key {
         let redirect = {
-            match self.value(vid).parent(vid) {
+            match self.parent(vid) {
                 None => return vid,
                 Some(redirect) => redirect,
             }
@@ -391,26 +783,37 @@ impl<S: UnificationStoreMut> UnificationTable<S> {
     // 'inlined_get_root_key` is the always-inlined version.
     #[inline(never)]
     fn uninlined_get_root_key(&mut self, vid: S::Key) -> S::Key {
-        self.inlined_get_root_key(vid)
+        St::Find::find_root(&mut self.values, vid)
     }
 
     // read-only version of `uninlined_get_root_key`, no path-compression
     #[inline(never)]
     fn read_uninlined_get_root_key(&self, vid: S::Key) -> S::Key {
-        self.read_inlined_get_root_key(vid)
+        let mut current = vid;
+        while let Some(parent) = self.parent(current) {
+            current = parent;
+        }
+        current
     }
 
     fn update_value<OP>(&mut self, key: S::Key, op: OP)
     where
         OP: FnOnce(&mut VarValue<S::Key>),
     {
-        self.values.update(key.index() as usize, op);
-        debug!("Updated variable {:?} to {:?}", key, self.value(key));
+        self.values.update(key.index().as_usize(), op);
+        debug!(
+            "Updated variable {:?} to parent {:?}, rank {}, value {:?}",
+            key,
+            self.values.parent(key.index().as_usize()),
+            self.rank(key),
+            self.value(key)
+        );
     }
 
     /// Either redirects `node_a` to `node_b` or vice versa, depending
-    /// on the relative rank. The value associated with the new root
-    /// will be `new_value`.
+    /// on the relative rank, which is combined as chosen by the
+    /// `UnionStrategy` of the table. The value associated with the new
+    /// root will be `new_value`.
     ///
     /// NB: This is the "union" operation of "union-find". It is
     /// really more of a building block. If the values associated with
@@ -418,45 +821,34 @@ impl<S: UnificationStoreMut> UnificationTable<S> {
     /// `unify_var_var` below.
     fn unify_roots(&mut self, key_a: S::Key, key_b: S::Key, new_value: S::Value) {
         debug!("unify(key_a={:?}, key_b={:?})", key_a, key_b);
-
-        let rank_a = self.value(key_a).rank;
-        let rank_b = self.value(key_b).rank;
-        if let Some((new_root, redirected)) = S::Key::order_roots(
-            key_a,
-            &self.value(key_a).value,
-            key_b,
-            &self.value(key_b).value,
-        ) {
+        self.assert_live(key_a);
+        self.assert_live(key_b);
+
+        let rank_a = self.rank(key_a);
+        let rank_b = self.rank(key_b);
+        if let Some((new_root, redirected)) =
+            S::Key::order_roots(key_a, self.value(key_a), key_b, self.value(key_b))
+        {
             // compute the new rank for the new root that they chose;
             // this may not be the optimal choice.
             let new_rank = if new_root == key_a {
                 debug_assert!(redirected == key_b);
-                if rank_a > rank_b {
-                    rank_a
-                } else {
-                    rank_b + 1
-                }
+                St::Union::merge_ranks(rank_a, rank_b)
             } else {
                 debug_assert!(new_root == key_b);
                 debug_assert!(redirected == key_a);
-                if rank_b > rank_a {
-                    rank_b
-                } else {
-                    rank_a + 1
-                }
+                St::Union::merge_ranks(rank_b, rank_a)
             };
             self.redirect_root(new_rank, redirected, new_root, new_value);
         } else if rank_a > rank_b {
             // a has greater rank, so a should become b's parent,
             // i.e., b should redirect to a.
-            self.redirect_root(rank_a, key_b, key_a, new_value);
-        } else if rank_a < rank_b {
-            // b has greater rank, so a should redirect to b.
-            self.redirect_root(rank_b, key_a, key_b, new_value);
+            let new_rank = St::Union::merge_ranks(rank_a, rank_b);
+            self.redirect_root(new_rank, key_b, key_a, new_value);
         } else {
-            // If equal, redirect one to the other and increment the
-            // other's rank.
-            self.redirect_root(rank_a + 1, key_a, key_b, new_value);
+            // b has greater or equal rank, so a should redirect to b.
+            let new_rank = St::Union::merge_ranks(rank_b, rank_a);
+            self.redirect_root(new_rank, key_a, key_b, new_value);
         }
     }
 
@@ -471,20 +863,41 @@ impl<S: UnificationStoreMut> UnificationTable<S> {
         new_root_key: S::Key,
         new_value: S::Value,
     ) {
-        self.update_value(old_root_key, |old_root_value| {
-            old_root_value.redirect(new_root_key);
-        });
-        self.update_value(new_root_key, |new_root_value| {
-            new_root_value.root(new_rank, new_value);
-        });
+        let old_root_index = old_root_key.index().as_usize();
+        let new_root_index = new_root_key.index().as_usize();
+
+        // Only write the fields that change, so that the undo log does
+        // not need a copy of the old value of either root.
+        self.values.set_parent(old_root_index, new_root_key);
+        self.values.set_rank(new_root_index, new_rank);
+        self.values.set_value(new_root_index, new_value);
+
+        // Splice the two circular member lists together by swapping
+        // the `next` links of the two roots.
+        #[cfg(feature = "class-members")]
+        {
+            let old_root_next = self.values.next(old_root_index);
+            let new_root_next = self.values.next(new_root_index);
+            self.values.set_next(old_root_index, new_root_next);
+            self.values.set_next(new_root_index, old_root_next);
+        }
+
+        debug!(
+            "Redirected {:?} to {:?}, now with rank {} and value {:?}",
+            old_root_key,
+            new_root_key,
+            new_rank,
+            self.value(new_root_key)
+        );
     }
 }
 
 /// ////////////////////////////////////////////////////////////////////////
 /// Public API
 
-impl<S, K, V> UnificationTable<S>
+impl<S, K, V, St> UnificationTable<S, St>
 where
+    St: Strategy,
     S: UnificationStoreMut<Key = K, Value = V>,
     K: UnifyKey<Value = V>,
     V: UnifyValue,
@@ -512,6 +925,29 @@ where
         self.unify_var_value(id, value).unwrap();
     }
 
+    /// Unions each pair of keys in `pairs`; only applicable when unify
+    /// values use `NoError` as their error type.
+    pub fn union_all<I, K1, K2>(&mut self, pairs: I)
+    where
+        I: IntoIterator<Item = (K1, K2)>,
+        K1: Into<K>,
+        K2: Into<K>,
+        V: UnifyValue<Error = NoError>,
+    {
+        self.unify_all(pairs).unwrap();
+    }
+
+    /// Unions all of `keys` into a single class; only applicable when
+    /// unify values use `NoError` as their error type.
+    pub fn union_group<I, K1>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = K1>,
+        K1: Into<K>,
+        V: UnifyValue<Error = NoError>,
+    {
+        self.unify_group(keys).unwrap();
+    }
+
     /// Given two keys, indicates whether they have been unioned together.
     pub fn unioned<K1, K2>(&mut self, a_id: K1, b_id: K2) -> bool
     where
@@ -536,7 +972,9 @@ where
         K1: Into<K>,
     {
         let id = id.into();
-        self.uninlined_get_root_key(id)
+        let root = self.uninlined_get_root_key(id);
+        self.assert_live(root);
+        root
     }
 
     /// Given a key, returns the (current) root key, no path compression
@@ -545,7 +983,106 @@ where
             K1: Into<K>,
     {
         let id = id.into();
-        self.read_uninlined_get_root_key(id)
+        let root = self.read_uninlined_get_root_key(id);
+        self.assert_live(root);
+        root
+    }
+
+    /// Retires `id`, in time proportional to the size of its class with
+    /// the `class-members` feature, but to the size of the whole table
+    /// without it, since every key is then checked with `read_find`.
+    /// Programs that retire keys often, such as long-running ones that
+    /// keep reusing a table, should enable `class-members`.
+    ///
+    /// Retiring removes the key from its class, leaving the remaining
+    /// members unioned and the value of the class unchanged, and makes
+    /// its slot available for reuse by `new_key`. The key must not be
+    /// used again until `new_key` hands it out again; `find`,
+    /// `probe_value` and the unify methods panic if it is.
+    ///
+    /// Like any other change, retiring a key is undone by rolling back
+    /// a snapshot. Slots are only reused by the table that retired
+    /// them: a table returned by `UnificationTableStorage::with_log`
+    /// starts without any reusable slots every time, and the slots
+    /// retired through it are never reused.
+    pub fn retire_key<K1>(&mut self, id: K1)
+    where
+        K1: Into<K>,
+    {
+        let key = id.into();
+        assert!(
+            !self.is_retired(key),
+            "{}: key {:?} is already retired",
+            S::tag(),
+            key
+        );
+        debug!("{}: retiring key: {:?}", S::tag(), key);
+
+        let root = self.uninlined_get_root_key(key);
+
+        #[cfg(feature = "class-members")]
+        let members: Vec<K> = self.class_members(key).skip(1).collect();
+        #[cfg(not(feature = "class-members"))]
+        let members: Vec<K> = (0..self.len())
+            .map(|index| key_from_usize(index))
+            .filter(|&k| k != key && !self.is_retired(k) && self.read_find(k) == root)
+            .collect();
+
+        if let Some(&first) = members.first() {
+            // Everything pointing at `key` must point elsewhere. If
+            // `key` is the root, promote another member to take its
+            // place. Either way, the members whose parent is `key` are
+            // redirected to the root below; depending on the find
+            // strategy, there may be some even if `key` is not the root.
+            let rank = St::Union::retire_member(self.rank(root));
+            let new_root = if key == root {
+                let value = self.value(key).clone();
+                self.update_value(first, |new_root_value| {
+                    new_root_value.redirect(first);
+                    new_root_value.root(rank, value);
+                });
+                first
+            } else {
+                if rank != self.rank(root) {
+                    self.values.set_rank(root.index().as_usize(), rank);
+                }
+                root
+            };
+            for &member in &members {
+                let index = member.index().as_usize();
+                if member != new_root && self.values.parent(index) == key {
+                    self.values.set_parent(index, new_root);
+                }
+            }
+
+            #[cfg(feature = "class-members")]
+            {
+                // `members` lists the ring starting after `key`, so the
+                // last entry is the one whose `next` is `key`.
+                let last = *members.last().unwrap();
+                let next = self.values.next(key.index().as_usize());
+                self.values.set_next(last.index().as_usize(), next);
+            }
+        }
+
+        self.update_value(key, |value| value.retire(key));
+        self.retired.push(key);
+    }
+
+    /// Returns an iterator over every key in the same class as `id`,
+    /// starting with `id` itself. This takes time proportional to the
+    /// size of the class.
+    #[cfg(feature = "class-members")]
+    pub fn class_members<K1>(&self, id: K1) -> ClassMembers<'_, S>
+    where
+        K1: Into<K>,
+    {
+        let id = id.into();
+        ClassMembers {
+            values: &self.values,
+            start: id,
+            next: Some(id),
+        }
     }
 
     /// Unions together two variables, merging their values. If
@@ -561,16 +1098,53 @@ where
 
         let root_a = self.uninlined_get_root_key(a_id);
         let root_b = self.uninlined_get_root_key(b_id);
+        self.assert_live(root_a);
+        self.assert_live(root_b);
 
         if root_a == root_b {
             return Ok(());
         }
 
-        let combined = V::unify_values(&self.value(root_a).value, &self.value(root_b).value)?;
+        let combined = V::unify_values(self.value(root_a), self.value(root_b))?;
 
         Ok(self.unify_roots(root_a, root_b, combined))
     }
 
+    /// Unifies each pair of keys in `pairs`, in order, as with
+    /// `unify_var_var`. Stops at the first pair that fails to unify and
+    /// returns it along with the error; the pairs before it remain
+    /// unified. Use `commit_if_ok` to undo them as well.
+    pub fn unify_all<I, K1, K2>(&mut self, pairs: I) -> Result<(), (K, K, V::Error)>
+    where
+        I: IntoIterator<Item = (K1, K2)>,
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        for (a_id, b_id) in pairs {
+            let (a_id, b_id) = (a_id.into(), b_id.into());
+            if let Err(error) = self.unify_var_var(a_id, b_id) {
+                return Err((a_id, b_id, error));
+            }
+        }
+        Ok(())
+    }
+
+    /// Unifies all of `keys` into a single class, by unifying each of
+    /// them with the first. On failure, returns the first key and the
+    /// key that failed to unify with it, along with the error; the keys
+    /// before it remain unified, as with `unify_all`.
+    pub fn unify_group<I, K1>(&mut self, keys: I) -> Result<(), (K, K, V::Error)>
+    where
+        I: IntoIterator<Item = K1>,
+        K1: Into<K>,
+    {
+        let mut keys = keys.into_iter().map(Into::into);
+        match keys.next() {
+            None => Ok(()),
+            Some(first) => self.unify_all(keys.map(|key| (first, key))),
+        }
+    }
+
     /// Sets the value of the key `a_id` to `b`, attempting to merge
     /// with the previous value.
     pub fn unify_var_value<K1>(&mut self, a_id: K1, b: V) -> Result<(), V::Error>
@@ -579,8 +1153,14 @@ where
     {
         let a_id = a_id.into();
         let root_a = self.uninlined_get_root_key(a_id);
-        let value = V::unify_values(&self.value(root_a).value, &b)?;
-        self.update_value(root_a, |node| node.value = value);
+        self.assert_live(root_a);
+        let value = V::unify_values(self.value(root_a), &b)?;
+        self.values.set_value(root_a.index().as_usize(), value);
+        debug!(
+            "Updated variable {:?} to value {:?}",
+            root_a,
+            self.value(root_a)
+        );
         Ok(())
     }
 
@@ -609,7 +1189,8 @@ where
     {
         let id = id.into();
         let id = self.inlined_get_root_key(id);
-        self.value(id).value.clone()
+        self.assert_live(id);
+        self.value(id).clone()
     }
 
     // An always-inlined version of `probe_value`, for hot callsites.
@@ -620,7 +1201,75 @@ where
     {
         let id = id.into();
         let id = self.read_inlined_get_root_key(id);
-        self.value(id).value.clone()
+        self.assert_live(id);
+        self.value(id).clone()
+    }
+
+    /// Returns an iterator over the root key of every class, in
+    /// increasing order of key index.
+    pub fn roots(&self) -> impl Iterator<Item = K> + '_ {
+        self.roots_with_values().map(|(key, _)| key)
+    }
+
+    /// Returns an iterator over the root key of every class along with
+    /// the value of that class, in increasing order of key index.
+    pub fn roots_with_values(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        (0..self.len()).filter_map(move |index| {
+            let key = key_from_usize(index);
+            match self.parent(key) {
+                None if !self.is_retired(key) => Some((key, self.value(key).clone())),
+                _ => None,
+            }
+        })
+    }
+
+    /// Returns the number of distinct classes.
+    pub fn num_classes(&self) -> usize {
+        self.roots().count()
+    }
+
+    /// Groups every live key into its class. The classes are ordered by
+    /// their smallest key, and the keys within each class are in
+    /// increasing order. No path compression is performed.
+    pub fn partition(&self) -> Vec<Vec<K>> {
+        // Maps the index of each root to the index of its class in
+        // `classes`, assigned the first time we see a member.
+        let mut class_of_root: Vec<Option<usize>> = vec![None; self.len()];
+        let mut classes: Vec<Vec<K>> = vec![];
+        for index in 0..self.len() {
+            let key = key_from_usize(index);
+            if self.is_retired(key) {
+                continue;
+            }
+            let root = self.read_find(key);
+            let class = class_of_root[root.index().as_usize()].get_or_insert_with(|| {
+                classes.push(vec![]);
+                classes.len() - 1
+            });
+            classes[*class].push(key);
+        }
+        classes
+    }
+}
+
+/// Iterator over the members of a class; see
+/// `UnificationTable::class_members`.
+#[cfg(feature = "class-members")]
+pub struct ClassMembers<'a, S: UnificationStoreBase + 'a> {
+    values: &'a S,
+    start: S::Key,
+    next: Option<S::Key>,
+}
+
+#[cfg(feature = "class-members")]
+impl<'a, S: UnificationStoreBase> Iterator for ClassMembers<'a, S> {
+    type Item = S::Key;
+
+    fn next(&mut self) -> Option<S::Key> {
+        let key = self.next?;
+        let next = self.values.next(key.index().as_usize());
+        self.next = if next == self.start { None } else { Some(next) };
+        Some(key)
     }
 }
 
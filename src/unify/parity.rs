@@ -0,0 +1,172 @@
+//! Parity union-find, for 2-SAT style "same or opposite" constraints.
+//!
+//! A `ParityUnificationTable` relates keys by parity: two related keys
+//! are either in the *same* class or in *opposite* classes, and the
+//! relation composes as you would expect (the opposite of an opposite
+//! is the same). This is the special case of a
+//! `WeightedUnificationTable` whose offsets are drawn from Z/2, which
+//! we represent as `bool` under exclusive or: an offset of `true`
+//! means "different".
+
+use super::weighted::{WeightedSnapshot, WeightedUnificationTable};
+use super::{Group, UnifyKey};
+
+impl Group for bool {
+    fn identity() -> bool {
+        false
+    }
+
+    fn combine(&self, other: &bool) -> bool {
+        *self != *other
+    }
+
+    fn inverse(&self) -> bool {
+        *self
+    }
+}
+
+/// The known relation between two keys of a `ParityUnificationTable`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ParityRelation {
+    /// The keys are known to be in the same class.
+    Same,
+    /// The keys are known to be in opposite classes.
+    Different,
+    /// Nothing is known about the keys relative to one another.
+    Unknown,
+}
+
+impl ParityRelation {
+    fn from_offset(offset: Option<bool>) -> ParityRelation {
+        match offset {
+            Some(false) => ParityRelation::Same,
+            Some(true) => ParityRelation::Different,
+            None => ParityRelation::Unknown,
+        }
+    }
+}
+
+/// Table of keys related by parity. See the module documentation for
+/// details. As with `WeightedUnificationTable`, the `Value` type of
+/// `K` is not used.
+#[derive(Clone, Debug)]
+pub struct ParityUnificationTable<K: UnifyKey> {
+    table: WeightedUnificationTable<K, bool>,
+}
+
+/// Snapshot of a `ParityUnificationTable`, to be committed or rolled
+/// back in LIFO order.
+pub struct ParitySnapshot<K> {
+    snapshot: WeightedSnapshot<K, bool>,
+}
+
+// Manual impl avoids `Default` bound on `K`.
+impl<K: UnifyKey> Default for ParityUnificationTable<K> {
+    fn default() -> Self {
+        ParityUnificationTable {
+            table: WeightedUnificationTable::new(),
+        }
+    }
+}
+
+impl<K: UnifyKey> ParityUnificationTable<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of keys created so far.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Creates a fresh key, unrelated to any other key.
+    pub fn new_key(&mut self) -> K {
+        self.table.new_key()
+    }
+
+    /// Starts a new snapshot. Each snapshot must be either
+    /// rolled back or committed in a "LIFO" (stack) order.
+    pub fn snapshot(&mut self) -> ParitySnapshot<K> {
+        ParitySnapshot {
+            snapshot: self.table.snapshot(),
+        }
+    }
+
+    /// Reverses all changes since the last snapshot. Also
+    /// removes any keys that have been created since then.
+    pub fn rollback_to(&mut self, snapshot: ParitySnapshot<K>) {
+        self.table.rollback_to(snapshot.snapshot);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: ParitySnapshot<K>) {
+        self.table.commit(snapshot.snapshot);
+    }
+
+    /// Given a key, returns its (current) root key, and whether the key
+    /// is in the class opposite to its root.
+    pub fn find<K1: Into<K>>(&mut self, id: K1) -> (K, bool) {
+        self.table.find(id)
+    }
+
+    /// Returns the known relation between two keys.
+    pub fn relation<K1: Into<K>, K2: Into<K>>(&mut self, a_id: K1, b_id: K2) -> ParityRelation {
+        ParityRelation::from_offset(self.table.offset(a_id, b_id))
+    }
+
+    /// Records that `a_id` and `b_id` are in the same class. If they
+    /// are already known to be in opposite classes, returns
+    /// `Err((Different, Same))` and makes no change.
+    pub fn union_equal<K1, K2>(
+        &mut self,
+        a_id: K1,
+        b_id: K2,
+    ) -> Result<(), (ParityRelation, ParityRelation)>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        self.unify(a_id, b_id, false)
+    }
+
+    /// Records that `a_id` and `b_id` are in opposite classes. If they
+    /// are already known to be in the same class, returns
+    /// `Err((Same, Different))` and makes no change.
+    pub fn union_different<K1, K2>(
+        &mut self,
+        a_id: K1,
+        b_id: K2,
+    ) -> Result<(), (ParityRelation, ParityRelation)>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        self.unify(a_id, b_id, true)
+    }
+
+    fn unify<K1, K2>(
+        &mut self,
+        a_id: K1,
+        b_id: K2,
+        different: bool,
+    ) -> Result<(), (ParityRelation, ParityRelation)>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        self.table
+            .unify_var_var(a_id, b_id, different)
+            .map_err(|(known, requested)| {
+                (
+                    ParityRelation::from_offset(Some(known)),
+                    ParityRelation::from_offset(Some(requested)),
+                )
+            })
+    }
+}
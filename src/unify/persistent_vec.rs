@@ -0,0 +1,315 @@
+//! A persistent vector, used as the backing store of `Persistent`.
+//!
+//! `PersistentVec` is a 32-way trie whose nodes are shared through
+//! `Rc`s, so cloning it is O(1). Modifying an element only copies the
+//! nodes on the path to that element, and only those that are still
+//! shared with a clone. Since the unification table never removes
+//! elements (rolling back restores an older clone instead), the trie
+//! only supports `push`, updates in place and the bulk `set_all`.
+
+use std::fmt;
+use std::ops;
+use std::rc::Rc;
+
+const BITS: usize = 5;
+const BRANCH: usize = 1 << BITS;
+const MASK: usize = BRANCH - 1;
+
+pub struct PersistentVec<T> {
+    len: usize,
+    // Number of index bits consumed by the branches above the leaves;
+    // zero when the root is itself a leaf.
+    shift: usize,
+    root: Rc<Node<T>>,
+}
+
+#[derive(Clone)]
+enum Node<T> {
+    Branch(Vec<Rc<Node<T>>>),
+    Leaf(Vec<T>),
+}
+
+// Manual impl avoids `Clone` bound on `T`.
+impl<T> Clone for PersistentVec<T> {
+    fn clone(&self) -> Self {
+        PersistentVec {
+            len: self.len,
+            shift: self.shift,
+            root: self.root.clone(),
+        }
+    }
+}
+
+// Manual impl avoids `Default` bound on `T`.
+impl<T> Default for PersistentVec<T> {
+    fn default() -> Self {
+        PersistentVec {
+            len: 0,
+            shift: 0,
+            root: Rc::new(Node::Leaf(Vec::new())),
+        }
+    }
+}
+
+impl<T> PersistentVec<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+        let mut node = &*self.root;
+        let mut shift = self.shift;
+        loop {
+            match *node {
+                Node::Branch(ref children) => {
+                    node = &children[(index >> shift) & MASK];
+                    shift -= BITS;
+                }
+                Node::Leaf(ref values) => return Some(&values[index & MASK]),
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.len).map(move |index| &self[index])
+    }
+
+    /// Returns a mutable reference to an element if no node on its path
+    /// is shared with another clone, and `None` otherwise (or if the
+    /// index is out of bounds). Unlike `get_mut`, this never copies.
+    pub fn get_mut_unshared(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+        let mut node = Rc::get_mut(&mut self.root)?;
+        let mut shift = self.shift;
+        loop {
+            let current = node;
+            match current {
+                Node::Branch(children) => {
+                    node = Rc::get_mut(&mut children[(index >> shift) & MASK])?;
+                    shift -= BITS;
+                }
+                Node::Leaf(values) => return Some(&mut values[index & MASK]),
+            }
+        }
+    }
+
+    /// Replaces every element `i` with `value(i)`. Nodes that are not
+    /// shared are updated in place; shared ones are rebuilt directly
+    /// from the new values, without copying the old ones first.
+    pub fn set_all(&mut self, mut value: impl FnMut(usize) -> T) {
+        let mut index = 0;
+        Self::fill(&mut self.root, &mut index, &mut value);
+        debug_assert_eq!(index, self.len);
+    }
+
+    fn fill(node: &mut Rc<Node<T>>, index: &mut usize, value: &mut impl FnMut(usize) -> T) {
+        let rebuilt = match Rc::get_mut(node) {
+            Some(&mut Node::Branch(ref mut children)) => {
+                for child in children {
+                    Self::fill(child, index, value);
+                }
+                return;
+            }
+            Some(&mut Node::Leaf(ref mut values)) => {
+                for slot in values {
+                    *slot = value(*index);
+                    *index += 1;
+                }
+                return;
+            }
+            None => Self::rebuild(node, index, value),
+        };
+        *node = Rc::new(rebuilt);
+    }
+
+    fn rebuild(node: &Node<T>, index: &mut usize, value: &mut impl FnMut(usize) -> T) -> Node<T> {
+        match *node {
+            Node::Branch(ref children) => {
+                let mut new_children = Vec::with_capacity(children.len());
+                for child in children {
+                    new_children.push(Rc::new(Self::rebuild(child, index, value)));
+                }
+                Node::Branch(new_children)
+            }
+            Node::Leaf(ref values) => {
+                let mut new_values = Vec::with_capacity(values.len());
+                for _ in 0..values.len() {
+                    new_values.push(value(*index));
+                    *index += 1;
+                }
+                Node::Leaf(new_values)
+            }
+        }
+    }
+}
+
+impl<T: Clone> PersistentVec<T> {
+    pub fn push(&mut self, value: T) {
+        if self.len == BRANCH << self.shift {
+            // The trie is full: add a level above the current root.
+            let old_root = self.root.clone();
+            self.root = Rc::new(Node::Branch(vec![old_root]));
+            self.shift += BITS;
+        }
+
+        let index = self.len;
+        let mut node = Rc::make_mut(&mut self.root);
+        let mut shift = self.shift;
+        loop {
+            // Move `node` out, so that its children can be borrowed for
+            // as long as the original reference.
+            let current = node;
+            match current {
+                Node::Branch(children) => {
+                    let child = (index >> shift) & MASK;
+                    if child == children.len() {
+                        children.push(Rc::new(if shift == BITS {
+                            Node::Leaf(Vec::with_capacity(BRANCH))
+                        } else {
+                            Node::Branch(Vec::with_capacity(BRANCH))
+                        }));
+                    }
+                    node = Rc::make_mut(&mut children[child]);
+                    shift -= BITS;
+                }
+                Node::Leaf(values) => {
+                    values.push(value);
+                    break;
+                }
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Returns a mutable reference to an element, first copying the
+    /// nodes on its path that are shared with other clones.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+        let mut node = Rc::make_mut(&mut self.root);
+        let mut shift = self.shift;
+        loop {
+            // Move `node` out, so that its children can be borrowed for
+            // as long as the original reference.
+            let current = node;
+            match current {
+                Node::Branch(children) => {
+                    node = Rc::make_mut(&mut children[(index >> shift) & MASK]);
+                    shift -= BITS;
+                }
+                Node::Leaf(values) => return Some(&mut values[index & MASK]),
+            }
+        }
+    }
+}
+
+impl<T> ops::Index<usize> for PersistentVec<T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(value) => value,
+            None => panic!("index {} out of bounds (len {})", index, self.len),
+        }
+    }
+}
+
+impl<T: Clone> ops::IndexMut<usize> for PersistentVec<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        let len = self.len;
+        match self.get_mut(index) {
+            Some(value) => value,
+            None => panic!("index {} out of bounds (len {})", index, len),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PersistentVec<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[test]
+fn push_and_get() {
+    let mut vec = PersistentVec::new();
+    for i in 0..2000 {
+        vec.push(i);
+    }
+    assert_eq!(vec.len(), 2000);
+    for i in 0..2000 {
+        assert_eq!(vec[i], i);
+    }
+    assert_eq!(vec.get(2000), None);
+}
+
+#[test]
+fn clones_are_independent() {
+    let mut vec = PersistentVec::new();
+    for i in 0..100 {
+        vec.push(i);
+    }
+    let snapshot = vec.clone();
+    vec[5] = 500;
+    vec.push(100);
+    assert_eq!(vec[5], 500);
+    assert_eq!(snapshot[5], 5);
+    assert_eq!(snapshot.len(), 100);
+
+    // Only the path to element 5 was copied.
+    assert!(Rc::ptr_eq(&branch(&vec)[1], &branch(&snapshot)[1]));
+    assert!(!Rc::ptr_eq(&branch(&vec)[0], &branch(&snapshot)[0]));
+}
+
+#[test]
+fn get_mut_unshared() {
+    let mut vec = PersistentVec::new();
+    for i in 0..100 {
+        vec.push(i);
+    }
+    *vec.get_mut_unshared(5).unwrap() = 500;
+
+    let snapshot = vec.clone();
+    assert_eq!(vec.get_mut_unshared(5), None);
+    vec[40] = 400;
+    assert_eq!(vec.get_mut_unshared(5), None);
+    *vec.get_mut_unshared(41).unwrap() = 410;
+    assert_eq!(snapshot[41], 41);
+
+    drop(snapshot);
+    *vec.get_mut_unshared(5).unwrap() = 50;
+    assert_eq!(vec[5], 50);
+    assert_eq!(vec.get_mut_unshared(100), None);
+}
+
+#[test]
+fn set_all_shared() {
+    let mut vec = PersistentVec::new();
+    for i in 0..1100 {
+        vec.push(i);
+    }
+    let snapshot = vec.clone();
+    vec.set_all(|i| i * 2);
+    assert!(vec.iter().enumerate().all(|(i, &v)| v == i * 2));
+    assert!(snapshot.iter().enumerate().all(|(i, &v)| v == i));
+
+    vec.set_all(|i| i * 3);
+    assert!(vec.iter().enumerate().all(|(i, &v)| v == i * 3));
+}
+
+#[cfg(test)]
+fn branch<T>(vec: &PersistentVec<T>) -> &[Rc<Node<T>>] {
+    match *vec.root {
+        Node::Branch(ref children) => children,
+        Node::Leaf(_) => panic!("root is a leaf"),
+    }
+}
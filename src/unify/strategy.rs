@@ -0,0 +1,192 @@
+//! Strategies for the union and find operations of a
+//! `UnificationTable`.
+//!
+//! The strategy is the last type parameter of the table and defaults
+//! to `DefaultStrategy`, union-by-rank with full path compression. It
+//! is a pair `(U, F)` of a `UnionStrategy` and a `FindStrategy`, so a
+//! table using union-by-size and path halving is written
+//! `UnificationTable<InPlace<K>, (BySize, PathHalving)>`. All
+//! combinations give the same classes; they only differ in the shape
+//! of the trees, and so in the cost of each operation.
+
+use super::{UnificationStoreMut, UnifyIndex, UnifyKey, RETIRED_RANK};
+
+/// Selects the union and find strategies of a `UnificationTable`.
+pub trait Strategy {
+    type Union: UnionStrategy;
+    type Find: FindStrategy;
+}
+
+impl<U: UnionStrategy, F: FindStrategy> Strategy for (U, F) {
+    type Union = U;
+    type Find = F;
+}
+
+/// The strategy used when none is given: union-by-rank with full path
+/// compression.
+pub type DefaultStrategy = (ByRank, FullCompression);
+
+/// Decides which root wins a union. Each root carries a weight, stored
+/// in the rank of its `VarValue`; the root with the greater weight
+/// becomes the parent of the other, unless `UnifyKey::order_roots`
+/// says otherwise.
+pub trait UnionStrategy {
+    /// The weight of a newly created key.
+    fn initial_rank() -> u32;
+
+    /// The weight of `winner` once the root with weight `loser` has
+    /// been redirected to it. The result must be less than `u32::MAX`,
+    /// which the table uses to mark retired keys.
+    fn merge_ranks(winner: u32, loser: u32) -> u32;
+
+    /// The weight of a root once another member of its class has been
+    /// retired.
+    fn retire_member(rank: u32) -> u32 {
+        rank
+    }
+}
+
+/// Union-by-rank: the weight of a root bounds the height of its tree.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ByRank;
+
+impl UnionStrategy for ByRank {
+    #[inline]
+    fn initial_rank() -> u32 {
+        0
+    }
+
+    #[inline]
+    fn merge_ranks(winner: u32, loser: u32) -> u32 {
+        if winner > loser {
+            winner
+        } else {
+            loser + 1
+        }
+    }
+}
+
+/// Union-by-size: the weight of a root is the number of keys in its
+/// class. Sizes saturate just below `u32::MAX`, past which the weights
+/// only approximate the sizes.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BySize;
+
+impl UnionStrategy for BySize {
+    #[inline]
+    fn initial_rank() -> u32 {
+        1
+    }
+
+    #[inline]
+    fn merge_ranks(winner: u32, loser: u32) -> u32 {
+        winner.saturating_add(loser).min(RETIRED_RANK - 1)
+    }
+
+    #[inline]
+    fn retire_member(rank: u32) -> u32 {
+        rank - 1
+    }
+}
+
+/// Decides how `find` walks from a key to its root, and which of the
+/// keys on the way it redirects closer to the root. All strategies
+/// provided here are iterative, so deep chains cannot overflow the
+/// stack.
+pub trait FindStrategy {
+    /// Returns the root of `key`, possibly compressing its path.
+    fn find_root<S: UnificationStoreMut>(values: &mut S, key: S::Key) -> S::Key;
+}
+
+/// Points every key on the path directly at the root.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FullCompression;
+
+impl FindStrategy for FullCompression {
+    fn find_root<S: UnificationStoreMut>(values: &mut S, key: S::Key) -> S::Key {
+        let root = NoCompression::find_root(values, key);
+
+        let mut current = key;
+        while current != root {
+            let parent = values.parent(current.index().as_usize());
+            if parent != root {
+                compress(values, current, root);
+            }
+            current = parent;
+        }
+        root
+    }
+}
+
+/// Points every other key on the path at its grandparent, in a single
+/// pass.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PathHalving;
+
+impl FindStrategy for PathHalving {
+    fn find_root<S: UnificationStoreMut>(values: &mut S, key: S::Key) -> S::Key {
+        let mut current = key;
+        loop {
+            let parent = match parent_of(values, current) {
+                None => return current,
+                Some(parent) => parent,
+            };
+            let grandparent = match parent_of(values, parent) {
+                None => return parent,
+                Some(grandparent) => grandparent,
+            };
+            compress(values, current, grandparent);
+            current = grandparent;
+        }
+    }
+}
+
+/// Points every key on the path at its grandparent, in a single pass.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PathSplitting;
+
+impl FindStrategy for PathSplitting {
+    fn find_root<S: UnificationStoreMut>(values: &mut S, key: S::Key) -> S::Key {
+        let mut current = key;
+        loop {
+            let parent = match parent_of(values, current) {
+                None => return current,
+                Some(parent) => parent,
+            };
+            let grandparent = match parent_of(values, parent) {
+                None => return parent,
+                Some(grandparent) => grandparent,
+            };
+            compress(values, current, grandparent);
+            current = parent;
+        }
+    }
+}
+
+/// Never modifies the table; `find` costs the height of the tree.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoCompression;
+
+impl FindStrategy for NoCompression {
+    fn find_root<S: UnificationStoreMut>(values: &mut S, key: S::Key) -> S::Key {
+        let mut current = key;
+        while let Some(parent) = parent_of(values, current) {
+            current = parent;
+        }
+        current
+    }
+}
+
+fn parent_of<S: UnificationStoreMut>(values: &S, key: S::Key) -> Option<S::Key> {
+    let parent = values.parent(key.index().as_usize());
+    if parent == key {
+        None
+    } else {
+        Some(parent)
+    }
+}
+
+fn compress<S: UnificationStoreMut>(values: &mut S, key: S::Key, ancestor: S::Key) {
+    values.compress_path(key.index().as_usize(), ancestor);
+    debug!("Compressed path of {:?} to {:?}", key, ancestor);
+}
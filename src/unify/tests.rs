@@ -16,17 +16,30 @@
 extern crate test;
 #[cfg(feature = "bench")]
 use self::test::Bencher;
+use std::cell::Cell;
 use std::cmp;
+use std::panic;
+use std::thread;
+use undo_log::SnapshotError;
+use unify::ConcurrentUnificationTable;
 #[cfg(feature = "persistent")]
 use unify::Persistent;
-use unify::{EqUnifyValue, InPlace, InPlaceUnificationTable, NoError, UnifyKey, UnifyValue};
-use unify::{UnificationStore, UnificationTable};
+use unify::{ByRank, BySize, DefaultStrategy, Strategy, UnionStrategy};
+use unify::{
+    EqUnifyValue, InPlace, InPlaceUnificationTable, NoError, Packed, UnifyKey, UnifyValue,
+};
+use unify::{ExplainedUnificationTable, UnificationStore, UnificationTable};
+use unify::{FrozenError, FrozenUnificationTable, KeyOverflowError, UnitCodec, ValueCodec};
+use unify::{FullCompression, NoCompression, PathHalving, PathSplitting};
+use unify::{Group, ParityRelation, ParityUnificationTable, WeightedUnificationTable};
+use unify::{UnificationStoreBase, UnificationStoreMut};
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 struct UnitKey(u32);
 
 impl UnifyKey for UnitKey {
     type Value = ();
+    type Index = u32;
     fn index(&self) -> u32 {
         self.0
     }
@@ -48,6 +61,8 @@ macro_rules! all_modes {
 
         test_body::<InPlace<$t>>();
 
+        test_body::<Packed<$t>>();
+
         #[cfg(feature = "persistent")]
         test_body::<Persistent<$t>>();
     };
@@ -126,6 +141,12 @@ fn big_array_bench_InPlace(b: &mut Bencher) {
     big_array_bench_generic::<InPlace<UnitKey>>(b);
 }
 
+#[cfg(feature = "bench")]
+#[bench]
+fn big_array_bench_Packed(b: &mut Bencher) {
+    big_array_bench_generic::<Packed<UnitKey>>(b);
+}
+
 #[cfg(all(feature = "bench", feature = "persistent"))]
 #[bench]
 fn big_array_bench_Persistent(b: &mut Bencher) {
@@ -167,6 +188,12 @@ fn big_array_bench_in_snapshot_InPlace(b: &mut Bencher) {
     big_array_bench_in_snapshot_generic::<InPlace<UnitKey>>(b);
 }
 
+#[cfg(feature = "bench")]
+#[bench]
+fn big_array_bench_in_snapshot_Packed(b: &mut Bencher) {
+    big_array_bench_in_snapshot_generic::<Packed<UnitKey>>(b);
+}
+
 #[cfg(all(feature = "bench", feature = "persistent"))]
 #[bench]
 fn big_array_bench_in_snapshot_Persistent(b: &mut Bencher) {
@@ -210,12 +237,185 @@ fn big_array_bench_clone_InPlace(b: &mut Bencher) {
     big_array_bench_clone_generic::<InPlace<UnitKey>>(b);
 }
 
+#[cfg(feature = "bench")]
+#[bench]
+fn big_array_bench_clone_Packed(b: &mut Bencher) {
+    big_array_bench_clone_generic::<Packed<UnitKey>>(b);
+}
+
 #[cfg(all(feature = "bench", feature = "persistent"))]
 #[bench]
 fn big_array_bench_clone_Persistent(b: &mut Bencher) {
     big_array_bench_clone_generic::<Persistent<UnitKey>>(b);
 }
 
+#[cfg(feature = "bench")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct WideValue([u64; 8]);
+
+#[cfg(feature = "bench")]
+impl EqUnifyValue for WideValue {}
+
+#[cfg(feature = "bench")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct WideValueKey(u32);
+
+#[cfg(feature = "bench")]
+impl UnifyKey for WideValueKey {
+    type Value = Option<WideValue>;
+    type Index = u32;
+    fn index(&self) -> u32 {
+        self.0
+    }
+    fn from_index(u: u32) -> WideValueKey {
+        WideValueKey(u)
+    }
+    fn tag() -> &'static str {
+        "WideValueKey"
+    }
+}
+
+// Finds every key in a table whose values are much larger than the
+// parents. Path compression is disabled, so that every iteration walks
+// the same trees.
+#[cfg(feature = "bench")]
+fn wide_value_find_bench_generic<S>(b: &mut Bencher)
+where
+    S: Default + UnificationStore<Key = WideValueKey, Value = Option<WideValue>>,
+{
+    let mut ut: UnificationTable<S, (ByRank, NoCompression)> = UnificationTable::new();
+    const MAX: usize = 1 << 15;
+
+    let keys = ut.new_keys(MAX, |_| None);
+    for i in 1..MAX {
+        let l = WideValueKey(((i * 7919) % MAX) as u32);
+        let r = WideValueKey((((i - 1) * 7919) % MAX) as u32);
+        ut.unify_var_var(l, r).unwrap();
+    }
+    let root = ut.find(keys.start);
+
+    b.iter(|| {
+        for i in 0..MAX {
+            assert_eq!(ut.find(WideValueKey(i as u32)), root);
+        }
+    })
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn wide_value_find_bench_InPlace(b: &mut Bencher) {
+    wide_value_find_bench_generic::<InPlace<WideValueKey>>(b);
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn wide_value_find_bench_Packed(b: &mut Bencher) {
+    wide_value_find_bench_generic::<Packed<WideValueKey>>(b);
+}
+
+#[cfg(feature = "bench")]
+fn strategy_bench_generic<S, St>(b: &mut Bencher)
+where
+    S: Default + UnificationStore<Key = UnitKey, Value = ()>,
+    St: Strategy,
+{
+    let mut ut: UnificationTable<S, St> = UnificationTable::new();
+    let mut keys = Vec::new();
+    const MAX: usize = 1 << 15;
+
+    for _ in 0..MAX {
+        keys.push(ut.new_key(()));
+    }
+
+    b.iter(|| {
+        let snapshot = ut.snapshot();
+
+        // Union in a scattered order, so that the trees are not all
+        // as shallow as a chain unioned from one end.
+        for i in 1..MAX {
+            let l = keys[(i * 7919) % MAX];
+            let r = keys[((i - 1) * 7919) % MAX];
+            ut.union(l, r);
+        }
+
+        for i in 0..MAX {
+            assert!(ut.unioned(keys[0], keys[i]));
+        }
+
+        ut.rollback_to(snapshot);
+    })
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn strategy_bench_ByRank_FullCompression(b: &mut Bencher) {
+    strategy_bench_generic::<InPlace<UnitKey>, (ByRank, FullCompression)>(b);
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn strategy_bench_ByRank_PathHalving(b: &mut Bencher) {
+    strategy_bench_generic::<InPlace<UnitKey>, (ByRank, PathHalving)>(b);
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn strategy_bench_ByRank_PathSplitting(b: &mut Bencher) {
+    strategy_bench_generic::<InPlace<UnitKey>, (ByRank, PathSplitting)>(b);
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn strategy_bench_ByRank_NoCompression(b: &mut Bencher) {
+    strategy_bench_generic::<InPlace<UnitKey>, (ByRank, NoCompression)>(b);
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn strategy_bench_BySize_FullCompression(b: &mut Bencher) {
+    strategy_bench_generic::<InPlace<UnitKey>, (BySize, FullCompression)>(b);
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn strategy_bench_BySize_PathHalving(b: &mut Bencher) {
+    strategy_bench_generic::<InPlace<UnitKey>, (BySize, PathHalving)>(b);
+}
+
+#[cfg(all(feature = "bench", feature = "persistent"))]
+#[bench]
+fn strategy_bench_Persistent_FullCompression(b: &mut Bencher) {
+    strategy_bench_generic::<Persistent<UnitKey>, (ByRank, FullCompression)>(b);
+}
+
+#[cfg(all(feature = "bench", feature = "persistent"))]
+#[bench]
+fn strategy_bench_Persistent_PathHalving(b: &mut Bencher) {
+    strategy_bench_generic::<Persistent<UnitKey>, (ByRank, PathHalving)>(b);
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn retire_key_bench(b: &mut Bencher) {
+    // Without the `class-members` feature, every retirement scans the
+    // whole table, so this grows with `MAX`.
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    const MAX: usize = 1 << 12;
+
+    for _ in 0..MAX {
+        ut.new_key(());
+    }
+
+    b.iter(|| {
+        // The retired slots are reused, so the table does not grow.
+        let k1 = ut.new_key(());
+        let k2 = ut.new_key(());
+        ut.union(k1, k2);
+        ut.retire_key(k1);
+        ut.retire_key(k2);
+    })
+}
+
 #[test]
 fn even_odd() {
     all_modes! {
@@ -247,10 +447,12 @@ fn even_odd() {
 }
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 struct IntKey(u32);
 
 impl UnifyKey for IntKey {
     type Value = Option<i32>;
+    type Index = u32;
     fn index(&self) -> u32 {
         self.0
     }
@@ -377,6 +579,7 @@ struct OrderedRank(u32);
 
 impl UnifyKey for OrderedKey {
     type Value = OrderedRank;
+    type Index = u32;
     fn index(&self) -> u32 {
         self.0
     }
@@ -498,3 +701,1388 @@ fn clone_table() {
         }
     }
 }
+
+#[test]
+fn explain_chain() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: ExplainedUnificationTable<S, u32> = ExplainedUnificationTable::new();
+            let k: Vec<UnitKey> = (0..5).map(|_| ut.new_key(())).collect();
+            ut.union(k[0], k[1], 1);
+            ut.union(k[2], k[3], 2);
+            ut.union(k[1], k[2], 3);
+            ut.union(k[0], k[3], 4); // redundant, no new proof edge
+
+            assert_eq!(ut.explain(k[0], k[3]), Some(vec![&1, &3, &2]));
+            assert_eq!(ut.explain(k[3], k[0]), Some(vec![&2, &3, &1]));
+            assert_eq!(ut.explain(k[1], k[2]), Some(vec![&3]));
+            assert_eq!(ut.explain(k[2], k[2]), Some(vec![]));
+            assert_eq!(ut.explain(k[0], k[4]), None);
+        }
+    }
+}
+
+#[test]
+fn explain_reroot() {
+    all_modes! {
+        S for UnitKey => {
+            // Build two chains and join them in the middle, which forces
+            // one of the proof trees to be re-rooted.
+            let mut ut: ExplainedUnificationTable<S, &'static str> = ExplainedUnificationTable::new();
+            let k: Vec<UnitKey> = (0..6).map(|_| ut.new_key(())).collect();
+            ut.union(k[0], k[1], "a");
+            ut.union(k[1], k[2], "b");
+            ut.union(k[3], k[4], "c");
+            ut.union(k[4], k[5], "d");
+            ut.union(k[1], k[4], "e");
+
+            assert_eq!(ut.explain(k[0], k[5]), Some(vec![&"a", &"e", &"d"]));
+            assert_eq!(ut.explain(k[2], k[3]), Some(vec![&"b", &"e", &"c"]));
+            assert_eq!(ut.explain(k[5], k[2]), Some(vec![&"d", &"e", &"b"]));
+        }
+    }
+}
+
+#[test]
+fn explain_rollback() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: ExplainedUnificationTable<S, u32> = ExplainedUnificationTable::new();
+            let k: Vec<UnitKey> = (0..4).map(|_| ut.new_key(())).collect();
+            ut.union(k[0], k[1], 1);
+            ut.union(k[2], k[3], 2);
+
+            let snapshot = ut.snapshot();
+            ut.union(k[3], k[0], 3);
+            assert_eq!(ut.explain(k[1], k[2]), Some(vec![&1, &3, &2]));
+            ut.rollback_to(snapshot);
+
+            assert_eq!(ut.explain(k[1], k[2]), None);
+            assert_eq!(ut.explain(k[0], k[1]), Some(vec![&1]));
+            assert_eq!(ut.explain(k[3], k[2]), Some(vec![&2]));
+
+            ut.union(k[1], k[2], 4);
+            assert_eq!(ut.explain(k[0], k[3]), Some(vec![&1, &4, &2]));
+        }
+    }
+}
+
+#[test]
+fn explain_failed_unify() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: ExplainedUnificationTable<S, u32> = ExplainedUnificationTable::new();
+            let k1 = ut.new_key(Some(22));
+            let k2 = ut.new_key(Some(23));
+            assert!(ut.unify_var_var(k1, k2, 1).is_err());
+            assert_eq!(ut.explain(k1, k2), None);
+        }
+    }
+}
+
+// A justification that cannot be cloned, so that proofs must move it.
+#[derive(Debug, PartialEq)]
+struct Why(u32);
+
+#[test]
+fn explain_long_chain() {
+    all_modes! {
+        S for UnitKey => {
+            // Each union joins a new key to the class built so far; only
+            // the proof tree of the new key is re-rooted.
+            let mut ut: ExplainedUnificationTable<S, Why> = ExplainedUnificationTable::new();
+            let k: Vec<UnitKey> = (0..1 << 12).map(|_| ut.new_key(())).collect();
+            for i in 1..k.len() {
+                ut.union(k[0], k[i], Why(i as u32));
+            }
+
+            let snapshot = ut.snapshot();
+            let extra = ut.new_key(());
+            ut.union(extra, k[7], Why(0));
+            assert_eq!(ut.explain(extra, k[3]), Some(vec![&Why(0), &Why(7), &Why(3)]));
+            ut.rollback_to(snapshot);
+
+            assert_eq!(ut.explain(k[5], k[9]), Some(vec![&Why(5), &Why(9)]));
+            assert_eq!(ut.explain(k[0], k[4095]), Some(vec![&Why(4095)]));
+        }
+    }
+}
+
+#[cfg(feature = "class-members")]
+#[test]
+fn class_members() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k: Vec<UnitKey> = (0..6).map(|_| ut.new_key(())).collect();
+            ut.union(k[0], k[1]);
+            ut.union(k[2], k[3]);
+            ut.union(k[3], k[4]);
+            ut.union(k[1], k[4]);
+
+            let mut members: Vec<UnitKey> = ut.class_members(k[2]).collect();
+            assert_eq!(members[0], k[2]);
+            members.sort_by_key(|k| k.0);
+            assert_eq!(members, &k[0..5]);
+
+            assert_eq!(ut.class_members(k[5]).collect::<Vec<_>>(), [k[5]]);
+        }
+    }
+}
+
+#[cfg(feature = "class-members")]
+#[test]
+fn class_members_rollback() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k: Vec<UnitKey> = (0..4).map(|_| ut.new_key(())).collect();
+            ut.union(k[0], k[1]);
+
+            let snapshot = ut.snapshot();
+            let k4 = ut.new_key(());
+            ut.union(k[2], k4);
+            ut.union(k[1], k[2]);
+            assert_eq!(ut.class_members(k[0]).count(), 4);
+            ut.rollback_to(snapshot);
+
+            let mut members: Vec<UnitKey> = ut.class_members(k[1]).collect();
+            members.sort_by_key(|k| k.0);
+            assert_eq!(members, [k[0], k[1]]);
+            assert_eq!(ut.class_members(k[2]).collect::<Vec<_>>(), [k[2]]);
+
+            ut.reset_unifications(|_| ());
+            assert_eq!(ut.class_members(k[0]).collect::<Vec<_>>(), [k[0]]);
+        }
+    }
+}
+
+#[test]
+fn partition() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            assert!(ut.partition().is_empty());
+            assert_eq!(ut.num_classes(), 0);
+
+            let k: Vec<UnitKey> = (0..6).map(|_| ut.new_key(())).collect();
+            ut.union(k[4], k[1]);
+            ut.union(k[3], k[5]);
+            ut.union(k[5], k[1]);
+
+            assert_eq!(ut.num_classes(), 3);
+            assert_eq!(
+                ut.partition(),
+                vec![vec![k[0]], vec![k[1], k[3], k[4], k[5]], vec![k[2]]]
+            );
+
+            let roots: Vec<UnitKey> = ut.roots().collect();
+            assert_eq!(roots.len(), 3);
+            assert!(roots.contains(&k[0]));
+            assert!(roots.contains(&k[2]));
+            assert!(roots.contains(&ut.find(k[5])));
+        }
+    }
+}
+
+#[test]
+fn roots_with_values() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(22));
+            let k3 = ut.new_key(None);
+            ut.unify_var_var(k1, k2).unwrap();
+
+            let root = ut.find(k1);
+            let mut roots: Vec<(IntKey, Option<i32>)> =
+                ut.roots_with_values().collect();
+            roots.sort_by_key(|&(k, _)| k.0);
+            let mut expected = vec![(root, Some(22)), (k3, None)];
+            expected.sort_by_key(|&(k, _)| k.0);
+            assert_eq!(roots, expected);
+        }
+    }
+}
+
+#[test]
+fn retire_root() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k: Vec<IntKey> = (0..4).map(|_| ut.new_key(None)).collect();
+            ut.unify_var_var(k[0], k[1]).unwrap();
+            ut.unify_var_var(k[0], k[2]).unwrap();
+            ut.unify_var_value(k[1], Some(22)).unwrap();
+
+            let root = ut.find(k[0]);
+            ut.retire_key(root);
+
+            let live: Vec<IntKey> = k[0..3].iter().cloned().filter(|&k| k != root).collect();
+            assert!(ut.unioned(live[0], live[1]));
+            assert_eq!(ut.probe_value(live[0]), Some(22));
+            assert_eq!(ut.num_classes(), 2);
+            assert_eq!(ut.partition(), vec![live.clone(), vec![k[3]]]);
+
+            // The retired slot is reused, as a fresh key.
+            let fresh = ut.new_key(None);
+            assert_eq!(fresh, root);
+            assert_eq!(ut.len(), 4);
+            assert!(!ut.unioned(fresh, live[0]));
+            assert_eq!(ut.probe_value(fresh), None);
+        }
+    }
+}
+
+#[test]
+fn retire_non_root() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k: Vec<UnitKey> = (0..8).map(|_| ut.new_key(())).collect();
+            for i in 1..8 {
+                ut.union(k[i - 1], k[i]);
+            }
+
+            let root = ut.find(k[0]);
+            let victim = if root == k[3] { k[4] } else { k[3] };
+            ut.retire_key(victim);
+            for &key in &k {
+                if key != victim {
+                    assert!(ut.unioned(key, root));
+                }
+            }
+            assert_eq!(ut.partition().len(), 1);
+            assert_eq!(ut.partition()[0].len(), 7);
+        }
+    }
+}
+
+#[test]
+fn retire_singleton() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            ut.retire_key(k1);
+            assert_eq!(ut.num_classes(), 1);
+            assert_eq!(ut.roots().collect::<Vec<_>>(), [k2]);
+            assert_eq!(ut.new_key(()), k1);
+            assert_eq!(ut.new_key(()), UnitKey(2));
+        }
+    }
+}
+
+#[test]
+fn retire_rollback() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k: Vec<UnitKey> = (0..3).map(|_| ut.new_key(())).collect();
+            ut.union(k[0], k[1]);
+            ut.union(k[1], k[2]);
+
+            let snapshot = ut.snapshot();
+            let root = ut.find(k[0]);
+            ut.retire_key(root);
+            // Retired slots are not reused while a snapshot is open.
+            let k3 = ut.new_key(());
+            assert_eq!(k3, UnitKey(3));
+            ut.rollback_to(snapshot);
+
+            assert_eq!(ut.len(), 3);
+            assert_eq!(ut.partition(), vec![k.clone()]);
+            assert_eq!(ut.find(k[2]), root);
+
+            // The stale hint left by the rolled back retirement is ignored.
+            assert_eq!(ut.new_key(()), UnitKey(3));
+        }
+    }
+}
+
+#[test]
+fn retire_commit_then_reuse() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            ut.union(k1, k2);
+
+            let snapshot = ut.snapshot();
+            ut.retire_key(k2);
+            ut.commit(snapshot);
+
+            assert_eq!(ut.new_key(()), k2);
+            assert!(!ut.unioned(k1, k2));
+        }
+    }
+}
+
+#[cfg(feature = "class-members")]
+#[test]
+fn retire_class_members() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k: Vec<UnitKey> = (0..5).map(|_| ut.new_key(())).collect();
+            for i in 1..5 {
+                ut.union(k[0], k[i]);
+            }
+            ut.retire_key(k[2]);
+            let mut members: Vec<UnitKey> = ut.class_members(k[4]).collect();
+            members.sort_by_key(|k| k.0);
+            assert_eq!(members, [k[0], k[1], k[3], k[4]]);
+            assert_eq!(ut.class_members(k[2]).collect::<Vec<_>>(), [k[2]]);
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "UnitKey: key UnitKey(1) is retired")]
+fn union_retired_key() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let k1 = ut.new_key(());
+    let k2 = ut.new_key(());
+    ut.retire_key(k2);
+    ut.union(k1, k2);
+}
+
+#[test]
+#[should_panic(expected = "IntKey: key IntKey(0) is retired")]
+fn probe_retired_key() {
+    let mut ut: InPlaceUnificationTable<IntKey> = UnificationTable::new();
+    let k1 = ut.new_key(Some(22));
+    ut.retire_key(k1);
+    ut.probe_value(k1);
+}
+
+#[test]
+#[should_panic(expected = "UnitKey: key UnitKey(0) is retired")]
+fn find_retired_key() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let k1 = ut.new_key(());
+    ut.retire_key(k1);
+    ut.find(k1);
+}
+
+#[test]
+fn probe() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(22));
+            let unioned = ut.probe(|ut| {
+                ut.new_key(None);
+                ut.unify_var_var(k1, k2).unwrap();
+                ut.unioned(k1, k2)
+            });
+            assert!(unioned);
+            assert!(!ut.unioned(k1, k2));
+            assert_eq!(ut.len(), 2);
+            assert_eq!(ut.probe_value(k1), None);
+        }
+    }
+}
+
+#[test]
+fn commit_if_ok() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(22));
+            let k3 = ut.new_key(Some(23));
+
+            // The first unification is undone along with the failed one.
+            let result = ut.commit_if_ok(|ut| {
+                ut.unify_var_var(k1, k2)?;
+                ut.unify_var_var(k1, k3)
+            });
+            assert_eq!(result, Err((22, 23)));
+            assert!(!ut.unioned(k1, k2));
+
+            let result = ut.commit_if_ok(|ut| ut.unify_var_var(k1, k2));
+            assert_eq!(result, Ok(()));
+            assert!(ut.unioned(k1, k2));
+        }
+    }
+}
+
+#[test]
+fn commit_unconditionally() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            let snapshot = ut.snapshot();
+            let k3 = ut.commit_unconditionally(|ut| {
+                ut.unify_var_var(k1, k2).unwrap();
+                ut.new_key(Some(1))
+            });
+            assert!(ut.unioned(k1, k2));
+            assert_eq!(ut.probe_value(k3), Some(1));
+            ut.rollback_to(snapshot);
+            assert!(!ut.unioned(k1, k2));
+            assert_eq!(ut.len(), 2);
+        }
+    }
+}
+
+#[test]
+fn snapshot_guard() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            {
+                let mut guard = ut.snapshot_guard();
+                let k3 = guard.new_key(Some(3));
+                guard.unify_var_var(k1, k3).unwrap();
+                assert_eq!(guard.vars_since_snapshot(), k3..IntKey(3));
+                assert_eq!(guard.probe_value(k1), Some(3));
+            }
+            assert_eq!(ut.len(), 2);
+            assert_eq!(ut.probe_value(k1), None);
+
+            let mut guard = ut.snapshot_guard();
+            guard.unify_var_var(k1, k2).unwrap();
+            guard.commit();
+            assert!(ut.unioned(k1, k2));
+        }
+    }
+}
+
+#[test]
+fn snapshot_guard_panic() {
+    let mut ut: InPlaceUnificationTable<IntKey> = UnificationTable::new();
+    let k1 = ut.new_key(None);
+    let k2 = ut.new_key(None);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut guard = ut.snapshot_guard();
+        guard.unify_var_var(k1, k2).unwrap();
+        panic!("oops");
+    }));
+    assert!(result.is_err());
+    assert!(!ut.unioned(k1, k2));
+
+    // No snapshot is left open, so the table can be frozen.
+    assert!(ut.write_frozen::<OptionI32Codec, _>(Vec::new()).is_ok());
+}
+
+#[test]
+fn try_rollback_to() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let k1 = ut.new_key(());
+    let k2 = ut.new_key(());
+    let outer = ut.snapshot();
+    ut.union(k1, k2);
+    let inner = ut.snapshot();
+    ut.new_key(());
+
+    let (outer, err) = ut.try_commit(outer).unwrap_err();
+    assert_eq!(
+        err,
+        SnapshotError::OutOfOrder {
+            depth: 1,
+            innermost: 2
+        }
+    );
+    assert_eq!(ut.len(), 3);
+    assert!(ut.try_rollback_to(inner).is_ok());
+    assert_eq!(ut.len(), 2);
+    assert!(ut.unioned(k1, k2));
+
+    // The error handed the outer snapshot back, so it can still be
+    // committed, after which no snapshot is left open.
+    assert!(ut.try_commit(outer).is_ok());
+    assert!(ut.unioned(k1, k2));
+    assert!(ut.write_frozen::<UnitCodec, _>(Vec::new()).is_ok());
+
+    let mut other: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let foreign = other.snapshot();
+    assert_eq!(
+        ut.try_rollback_to(foreign).unwrap_err().1,
+        SnapshotError::Foreign
+    );
+}
+
+#[test]
+#[should_panic(expected = "snapshot at depth 1 used while the snapshot at depth 2 is still open")]
+fn rollback_out_of_order() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let outer = ut.snapshot();
+    let _inner = ut.snapshot();
+    ut.rollback_to(outer);
+}
+
+fn ordered_chain<St: Strategy>(len: u32) -> UnificationTable<InPlace<OrderedKey>, St> {
+    // Each key outranks the previous one, so unioning them in order
+    // builds a single chain rooted at the last key.
+    let mut ut: UnificationTable<InPlace<OrderedKey>, St> = UnificationTable::new();
+    for i in 0..len {
+        let key = ut.new_key(OrderedRank(i));
+        if i > 0 {
+            ut.union(OrderedKey(i - 1), key);
+        }
+    }
+    ut
+}
+
+fn parents<St: Strategy>(ut: &UnificationTable<InPlace<OrderedKey>, St>) -> Vec<u32> {
+    (0..ut.len()).map(|i| ut.values[i].parent.0).collect()
+}
+
+#[test]
+fn find_strategies() {
+    let mut ut = ordered_chain::<(ByRank, FullCompression)>(6);
+    assert_eq!(ut.find(OrderedKey(0)), OrderedKey(5));
+    assert_eq!(parents(&ut), [5, 5, 5, 5, 5, 5]);
+
+    let mut ut = ordered_chain::<(ByRank, PathHalving)>(6);
+    assert_eq!(ut.find(OrderedKey(0)), OrderedKey(5));
+    assert_eq!(parents(&ut), [2, 2, 4, 4, 5, 5]);
+
+    let mut ut = ordered_chain::<(ByRank, PathSplitting)>(6);
+    assert_eq!(ut.find(OrderedKey(0)), OrderedKey(5));
+    assert_eq!(parents(&ut), [2, 3, 4, 5, 5, 5]);
+
+    let mut ut = ordered_chain::<(ByRank, NoCompression)>(6);
+    assert_eq!(ut.find(OrderedKey(0)), OrderedKey(5));
+    assert_eq!(parents(&ut), [1, 2, 3, 4, 5, 5]);
+}
+
+#[test]
+fn find_deep_chain() {
+    // Deep enough to overflow the stack if `find` were recursive.
+    const LEN: u32 = 1 << 20;
+    let mut ut = ordered_chain::<DefaultStrategy>(LEN);
+    assert_eq!(ut.find(OrderedKey(0)), OrderedKey(LEN - 1));
+    assert_eq!(ut.parent(OrderedKey(0)), Some(OrderedKey(LEN - 1)));
+}
+
+#[test]
+fn union_by_size() {
+    let mut ut: UnificationTable<InPlace<UnitKey>, (BySize, FullCompression)> =
+        UnificationTable::new();
+    let k: Vec<UnitKey> = (0..5).map(|_| ut.new_key(())).collect();
+    ut.union(k[0], k[1]);
+    ut.union(k[0], k[2]);
+    ut.union(k[3], k[4]);
+    assert_eq!(ut.rank(ut.read_find(k[0])), 3);
+    assert_eq!(ut.rank(ut.read_find(k[3])), 2);
+
+    // The larger class wins, whichever side it is on.
+    let root = ut.find(k[0]);
+    ut.union(k[3], k[0]);
+    assert_eq!(ut.find(k[4]), root);
+    assert_eq!(ut.rank(root), 5);
+}
+
+fn union_with_strategy<S, St>()
+where
+    S: Default + UnificationStore<Key = UnitKey, Value = ()>,
+    St: Strategy,
+{
+    let mut ut: UnificationTable<S, St> = UnificationTable::new();
+    let keys: Vec<UnitKey> = (0..100).map(|_| ut.new_key(())).collect();
+    // Union each key with an earlier one of the same parity, in an
+    // order that builds trees of varying shapes.
+    for i in 2..100 {
+        ut.union(keys[i], keys[i % 2 + 2 * ((i * 13 + 5) % (i / 2))]);
+    }
+    for i in 0..100 {
+        assert_eq!(ut.unioned(keys[0], keys[i]), i % 2 == 0);
+        assert_eq!(ut.read_unioned(keys[1], keys[i]), i % 2 == 1);
+    }
+}
+
+#[test]
+fn all_strategies() {
+    fn test_body<S: Default + UnificationStore<Key = UnitKey, Value = ()>>() {
+        union_with_strategy::<S, (ByRank, FullCompression)>();
+        union_with_strategy::<S, (ByRank, PathHalving)>();
+        union_with_strategy::<S, (ByRank, PathSplitting)>();
+        union_with_strategy::<S, (ByRank, NoCompression)>();
+        union_with_strategy::<S, (BySize, FullCompression)>();
+        union_with_strategy::<S, (BySize, PathHalving)>();
+        union_with_strategy::<S, (BySize, PathSplitting)>();
+        union_with_strategy::<S, (BySize, NoCompression)>();
+    }
+
+    test_body::<InPlace<UnitKey>>();
+
+    test_body::<Packed<UnitKey>>();
+
+    #[cfg(feature = "persistent")]
+    test_body::<Persistent<UnitKey>>();
+}
+
+#[cfg(feature = "persistent")]
+#[test]
+fn persistent_deferred_path_compression() {
+    let mut ut: UnificationTable<Persistent<UnitKey>> = UnificationTable::new();
+    let k: Vec<UnitKey> = (0..4).map(|_| ut.new_key(())).collect();
+    ut.union(k[0], k[1]);
+    ut.union(k[2], k[3]);
+    ut.union(k[0], k[2]);
+
+    // Find a key that is two steps away from its root.
+    let parent =
+        |ut: &UnificationTable<Persistent<UnitKey>>, key: UnitKey| ut.values[key.0 as usize].parent;
+    let deep = *k
+        .iter()
+        .find(|&&key| parent(&ut, parent(&ut, key)) != parent(&ut, key))
+        .unwrap();
+
+    let snapshot = ut.snapshot();
+    let root = ut.find(deep);
+    assert_ne!(parent(&ut, deep), root);
+    ut.commit(snapshot);
+
+    assert_eq!(ut.find(deep), root);
+    assert_eq!(parent(&ut, deep), root);
+}
+
+#[test]
+fn weighted_chain() {
+    let mut ut: WeightedUnificationTable<UnitKey, i32> = WeightedUnificationTable::new();
+    let a = ut.new_key();
+    let b = ut.new_key();
+    let c = ut.new_key();
+    assert_eq!(ut.offset(a, b), None);
+
+    // a = b + 1, b = c + 2
+    ut.unify_var_var(a, b, 1).unwrap();
+    ut.unify_var_var(b, c, 2).unwrap();
+    assert_eq!(ut.offset(a, c), Some(3));
+    assert_eq!(ut.offset(c, a), Some(-3));
+    assert_eq!(ut.offset(b, b), Some(0));
+
+    let (root, offset) = ut.read_find(a);
+    assert_eq!(ut.find(a), (root, offset));
+    assert_eq!(ut.offset(a, root), Some(offset));
+}
+
+#[test]
+fn weighted_inconsistent_cycle() {
+    let mut ut: WeightedUnificationTable<UnitKey, i64> = WeightedUnificationTable::new();
+    let k: Vec<UnitKey> = (0..4).map(|_| ut.new_key()).collect();
+    ut.unify_var_var(k[0], k[1], 5).unwrap();
+    ut.unify_var_var(k[2], k[3], -2).unwrap();
+    ut.unify_var_var(k[1], k[3], 10).unwrap();
+
+    // k0 = k1 + 5 = k3 + 15 = k2 + 17
+    assert!(ut.unify_var_var(k[0], k[2], 17).is_ok());
+    assert_eq!(ut.unify_var_var(k[0], k[2], 16), Err((17, 16)));
+    assert_eq!(ut.unify_var_var(k[2], k[0], 0), Err((-17, 0)));
+    assert_eq!(ut.offset(k[0], k[2]), Some(17));
+}
+
+#[test]
+fn weighted_rollback() {
+    let mut ut: WeightedUnificationTable<UnitKey, i32> = WeightedUnificationTable::new();
+    let a = ut.new_key();
+    let b = ut.new_key();
+    ut.unify_var_var(a, b, 4).unwrap();
+
+    let snapshot = ut.snapshot();
+    let c = ut.new_key();
+    ut.unify_var_var(c, a, 1).unwrap();
+    assert_eq!(ut.offset(c, b), Some(5));
+    ut.rollback_to(snapshot);
+
+    assert_eq!(ut.len(), 2);
+    assert_eq!(ut.offset(a, b), Some(4));
+
+    let snapshot = ut.snapshot();
+    let c = ut.new_key();
+    ut.unify_var_var(b, c, 1).unwrap();
+    ut.commit(snapshot);
+    assert_eq!(ut.offset(a, c), Some(5));
+}
+
+#[test]
+fn weighted_path_compression() {
+    // Build a long chain k[i] = k[i + 1] + 1 in an order that makes
+    // deep trees, then check offsets survive compression.
+    let mut ut: WeightedUnificationTable<UnitKey, i32> = WeightedUnificationTable::new();
+    let k: Vec<UnitKey> = (0..64).map(|_| ut.new_key()).collect();
+    let mut step = 1;
+    while step < 64 {
+        for i in (0..64).step_by(2 * step) {
+            ut.unify_var_var(k[i + step - 1], k[i + step], 1).unwrap();
+        }
+        step *= 2;
+    }
+    for (i, &key) in k.iter().enumerate() {
+        assert_eq!(ut.offset(key, k[63]), Some(63 - i as i32));
+    }
+    for &key in &k {
+        assert_eq!(ut.read_find(key), ut.find(key));
+    }
+}
+
+#[test]
+fn weighted_offsets_wrap() {
+    assert_eq!(i32::MIN.inverse(), i32::MIN);
+    assert_eq!(i32::MAX.combine(&1), i32::MIN);
+    assert_eq!(i8::MIN.combine(&i8::MIN), 0);
+
+    // a = b + MAX and b = c + MAX, so a = c - 2 modulo 2^32.
+    let mut ut: WeightedUnificationTable<UnitKey, i32> = WeightedUnificationTable::new();
+    let a = ut.new_key();
+    let b = ut.new_key();
+    let c = ut.new_key();
+    ut.unify_var_var(a, b, i32::MAX).unwrap();
+    ut.unify_var_var(b, c, i32::MAX).unwrap();
+    assert_eq!(ut.offset(a, c), Some(-2));
+    assert_eq!(ut.offset(c, a), Some(2));
+    assert_eq!(ut.offset(b, a), Some(i32::MIN + 1));
+    assert!(ut.unify_var_var(c, a, 2).is_ok());
+}
+
+#[test]
+fn parity_even_odd() {
+    // Like `even_odd`, but with the parity relation recorded natively:
+    // every key is different from its predecessor.
+    let mut ut: ParityUnificationTable<UnitKey> = ParityUnificationTable::new();
+    let mut keys = Vec::new();
+    const MAX: usize = 1 << 10;
+
+    for i in 0..MAX {
+        let key = ut.new_key();
+        keys.push(key);
+
+        if i >= 1 {
+            ut.union_different(key, keys[i - 1]).unwrap();
+        }
+    }
+
+    for i in 1..MAX {
+        assert_eq!(ut.relation(keys[i - 1], keys[i]), ParityRelation::Different);
+    }
+    for i in 2..MAX {
+        assert_eq!(ut.relation(keys[i - 2], keys[i]), ParityRelation::Same);
+    }
+    assert_eq!(
+        ut.relation(keys[0], keys[MAX - 1]),
+        ParityRelation::Different
+    );
+}
+
+#[test]
+fn parity_contradiction() {
+    let mut ut: ParityUnificationTable<UnitKey> = ParityUnificationTable::new();
+    let a = ut.new_key();
+    let b = ut.new_key();
+    let c = ut.new_key();
+    assert_eq!(ut.relation(a, b), ParityRelation::Unknown);
+
+    ut.union_different(a, b).unwrap();
+    ut.union_different(b, c).unwrap();
+    assert!(ut.union_equal(a, c).is_ok());
+    assert_eq!(
+        ut.union_different(c, a),
+        Err((ParityRelation::Same, ParityRelation::Different))
+    );
+    assert_eq!(
+        ut.union_equal(a, b),
+        Err((ParityRelation::Different, ParityRelation::Same))
+    );
+    assert_eq!(ut.relation(a, c), ParityRelation::Same);
+}
+
+#[test]
+fn parity_rollback() {
+    let mut ut: ParityUnificationTable<UnitKey> = ParityUnificationTable::new();
+    let a = ut.new_key();
+    let b = ut.new_key();
+
+    let snapshot = ut.snapshot();
+    ut.union_different(a, b).unwrap();
+    assert_eq!(ut.relation(a, b), ParityRelation::Different);
+    ut.rollback_to(snapshot);
+    assert_eq!(ut.relation(a, b), ParityRelation::Unknown);
+
+    let snapshot = ut.snapshot();
+    ut.union_equal(a, b).unwrap();
+    ut.commit(snapshot);
+    assert_eq!(ut.relation(b, a), ParityRelation::Same);
+}
+
+#[test]
+fn concurrent_basic() {
+    let mut ut: ConcurrentUnificationTable<IntKey> = ConcurrentUnificationTable::new();
+    let k1 = ut.new_key(None);
+    let k2 = ut.new_key(Some(22));
+    let k3 = ut.new_key(Some(23));
+    ut.unify_var_var(k1, k2).unwrap();
+    assert!(ut.unioned(k1, k2));
+    assert_eq!(ut.probe_value(k1), Some(22));
+    assert_eq!(ut.unify_var_var(k1, k3), Err((22, 23)));
+    assert!(!ut.unioned(k1, k3));
+    assert!(ut.unify_var_value(k3, Some(24)).is_err());
+    assert!(ut.unify_var_value(k1, Some(22)).is_ok());
+}
+
+#[test]
+fn concurrent_even_odd() {
+    // Several threads union overlapping chains of keys: every thread
+    // unions `i` with `i + 2` for its own share of the keys, so in the
+    // end there are exactly two classes, the even and the odd keys.
+    const MAX: usize = 1 << 12;
+    const THREADS: usize = 4;
+
+    let mut ut: ConcurrentUnificationTable<UnitKey> = ConcurrentUnificationTable::new();
+    let keys: Vec<UnitKey> = (0..MAX).map(|_| ut.new_key(())).collect();
+
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let ut = &ut;
+            let keys = &keys;
+            s.spawn(move || {
+                for i in (t..MAX - 2).step_by(THREADS) {
+                    ut.union(keys[i], keys[i + 2]);
+                    ut.find(keys[(i * 7) % MAX]);
+                }
+            });
+        }
+    });
+
+    for i in 2..MAX {
+        assert!(ut.unioned(keys[i - 2], keys[i]));
+        assert!(!ut.unioned(keys[i - 1], keys[i]));
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let mut ut: InPlaceUnificationTable<IntKey> = UnificationTable::new();
+    let k1 = ut.new_key(None);
+    let k2 = ut.new_key(Some(22));
+    let k3 = ut.new_key(None);
+    ut.unify_var_var(k1, k2).unwrap();
+
+    let snapshot = ut.snapshot();
+    assert!(serde_json::to_string(&ut).is_err());
+    ut.rollback_to(snapshot);
+
+    let json = serde_json::to_string(&ut).unwrap();
+    let mut ut: InPlaceUnificationTable<IntKey> = serde_json::from_str(&json).unwrap();
+    assert_eq!(ut.len(), 3);
+    assert!(ut.unioned(k1, k2));
+    assert!(!ut.unioned(k1, k3));
+    assert_eq!(ut.probe_value(k1), Some(22));
+
+    // The deserialized table is fully usable, snapshots included.
+    let snapshot = ut.snapshot();
+    ut.unify_var_var(k3, k1).unwrap();
+    assert_eq!(ut.probe_value(k3), Some(22));
+    ut.rollback_to(snapshot);
+    assert_eq!(ut.probe_value(k3), None);
+}
+
+/// Encodes `Option<i32>` as a presence byte followed by the value.
+struct OptionI32Codec;
+
+impl ValueCodec<Option<i32>> for OptionI32Codec {
+    const WIDTH: usize = 5;
+
+    fn encode(value: &Option<i32>, bytes: &mut [u8]) {
+        bytes[0] = value.is_some() as u8;
+        bytes[1..].copy_from_slice(&value.unwrap_or(0).to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<i32> {
+        let mut word = [0; 4];
+        word.copy_from_slice(&bytes[1..]);
+        if bytes[0] == 1 {
+            Some(i32::from_le_bytes(word))
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn frozen_round_trip() {
+    let mut ut: InPlaceUnificationTable<IntKey> = UnificationTable::new();
+    let k: Vec<IntKey> = (0..6).map(|_| ut.new_key(None)).collect();
+    ut.unify_var_var(k[0], k[1]).unwrap();
+    ut.unify_var_var(k[1], k[2]).unwrap();
+    ut.unify_var_var(k[3], k[4]).unwrap();
+    ut.unify_var_value(k[4], Some(7)).unwrap();
+
+    let mut bytes = vec![];
+    ut.write_frozen::<OptionI32Codec, _>(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 16 + 6 * 13);
+
+    let frozen: FrozenUnificationTable<IntKey, OptionI32Codec> =
+        FrozenUnificationTable::new(&bytes).unwrap();
+    assert_eq!(frozen.len(), 6);
+    for &a in &k {
+        assert_eq!(frozen.read_find(a), ut.find(a));
+        assert_eq!(frozen.read_probe_value(a), ut.probe_value(a));
+        for &b in &k {
+            assert_eq!(frozen.read_unioned(a, b), ut.unioned(a, b));
+        }
+    }
+}
+
+#[test]
+fn frozen_rejects_snapshots() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    ut.new_key(());
+    let snapshot = ut.snapshot();
+    assert!(ut.write_frozen::<UnitCodec, _>(vec![]).is_err());
+    ut.commit(snapshot);
+    assert!(ut.write_frozen::<UnitCodec, _>(vec![]).is_ok());
+}
+
+#[test]
+fn frozen_rejects_retired_keys() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let k1 = ut.new_key(());
+    ut.new_key(());
+    ut.retire_key(k1);
+    assert!(ut.write_frozen::<UnitCodec, _>(vec![]).is_err());
+    assert_eq!(ut.new_key(()), k1);
+    assert!(ut.write_frozen::<UnitCodec, _>(vec![]).is_ok());
+}
+
+#[test]
+fn frozen_validation() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let k1 = ut.new_key(());
+    let k2 = ut.new_key(());
+    ut.union(k1, k2);
+    let mut bytes = vec![];
+    ut.write_frozen::<UnitCodec, _>(&mut bytes).unwrap();
+
+    type Frozen<'a> = FrozenUnificationTable<'a, UnitKey, UnitCodec>;
+    assert!(Frozen::new(&bytes).is_ok());
+    assert_eq!(
+        FrozenUnificationTable::<IntKey, OptionI32Codec>::new(&bytes).err(),
+        Some(FrozenError::ValueWidth {
+            expected: 5,
+            found: 0
+        })
+    );
+    assert_eq!(
+        Frozen::new(&bytes[..bytes.len() - 1]).err(),
+        Some(FrozenError::BadLength {
+            expected: 32,
+            found: 31
+        })
+    );
+
+    let mut bad = bytes.clone();
+    bad[0] = b'X';
+    assert_eq!(Frozen::new(&bad).err(), Some(FrozenError::BadMagic));
+
+    let mut bad = bytes.clone();
+    bad[4] = 2;
+    assert_eq!(
+        Frozen::new(&bad).err(),
+        Some(FrozenError::UnsupportedVersion(2))
+    );
+
+    // Make the two keys point at each other.
+    let mut bad = bytes.clone();
+    let (k1_parent, k2_parent) = (16, 24);
+    bad[k1_parent] = 1;
+    bad[k2_parent] = 0;
+    assert_eq!(Frozen::new(&bad).err(), Some(FrozenError::BadParent(1)));
+}
+
+#[cfg(feature = "persistent")]
+#[test]
+fn persistent_foreign_snapshot() {
+    let mut ut: UnificationTable<Persistent<UnitKey>> = UnificationTable::new();
+    let k1 = ut.new_key(());
+    let k2 = ut.new_key(());
+    let snapshot = ut.snapshot();
+    ut.union(k1, k2);
+
+    // The other table has the same number of open snapshots, so only
+    // its id tells its snapshot apart.
+    let mut other: UnificationTable<Persistent<UnitKey>> = UnificationTable::new();
+    other.new_key(());
+    let foreign = other.snapshot();
+    assert_eq!(
+        ut.try_rollback_to(foreign).unwrap_err().1,
+        SnapshotError::Foreign
+    );
+    assert_eq!(ut.len(), 2);
+    assert!(ut.unioned(k1, k2));
+
+    let foreign = other.snapshot();
+    assert_eq!(
+        ut.try_commit(foreign).unwrap_err().1,
+        SnapshotError::Foreign
+    );
+    assert!(ut.try_rollback_to(snapshot).is_ok());
+    assert!(!ut.unioned(k1, k2));
+}
+
+#[test]
+fn packed_update() {
+    let mut ut: UnificationTable<Packed<IntKey>> = UnificationTable::new();
+    let k1 = ut.new_key(Some(1));
+    let k2 = ut.new_key(None);
+
+    // A rollback restores both the value and the packed parent and rank.
+    let snapshot = ut.snapshot();
+    ut.values.update(k1.index() as usize, |value| {
+        value.value = Some(2);
+        value.parent = k2;
+        value.rank = 3;
+    });
+    assert_eq!(ut.find(k1), k2);
+    ut.rollback_to(snapshot);
+    assert_eq!(ut.find(k1), k1);
+    assert_eq!(ut.values.rank(k1.index() as usize), 0);
+    assert_eq!(ut.probe_value(k1), Some(1));
+
+    // A panicking update leaves the old value in place.
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        ut.values
+            .update(k1.index() as usize, |_| panic!("update failed"));
+    }));
+    assert!(result.is_err());
+    assert_eq!(ut.probe_value(k1), Some(1));
+}
+
+#[test]
+fn new_keys() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(None);
+            let keys = ut.new_keys(3, |key| Some(key.0 as i32 * 10));
+            assert_eq!(keys, IntKey(1)..IntKey(4));
+            assert_eq!(ut.probe_value(IntKey(3)), Some(30));
+
+            let snapshot = ut.snapshot();
+            let keys = ut.new_keys(1000, |_| None);
+            assert_eq!(keys, IntKey(4)..IntKey(1004));
+            assert_eq!(ut.vars_since_snapshot(&snapshot), keys);
+            ut.unify_var_var(k0, IntKey(1003)).unwrap();
+            ut.rollback_to(snapshot);
+
+            assert_eq!(ut.len(), 4);
+            assert_eq!(ut.probe_value(k0), None);
+            assert_eq!(ut.new_keys(0, |_| None), IntKey(4)..IntKey(4));
+        }
+    }
+}
+
+#[test]
+fn new_keys_single_undo_entry() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let snapshot = ut.snapshot();
+    ut.new_keys(100, |_| ());
+    assert_eq!(
+        ut.values
+            .values
+            .actions_since_snapshot(&snapshot.snapshot)
+            .len(),
+        1
+    );
+    ut.commit(snapshot);
+}
+
+#[test]
+fn union_all() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys = (0..6).map(|_| ut.new_key(())).collect::<Vec<_>>();
+            ut.union_all(vec![(keys[0], keys[1]), (keys[2], keys[3])]);
+            ut.union_group(keys[3..].iter().cloned());
+            assert!(ut.unioned(keys[0], keys[1]));
+            assert!(!ut.unioned(keys[1], keys[2]));
+            assert!(ut.unioned(keys[2], keys[5]));
+            assert_eq!(ut.num_classes(), 2);
+        }
+    }
+}
+
+#[test]
+fn unify_all_reports_failing_pair() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(Some(1));
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(2));
+            let k3 = ut.new_key(None);
+
+            assert_eq!(
+                ut.unify_all(vec![(k0, k1), (k1, k2), (k2, k3)]),
+                Err((k1, k2, (1, 2)))
+            );
+            assert!(ut.unioned(k0, k1));
+            assert!(!ut.unioned(k2, k3));
+
+            assert_eq!(ut.unify_group(vec![k2, k3, k0]), Err((k2, k0, (2, 1))));
+            assert!(ut.unioned(k2, k3));
+            assert_eq!(ut.unify_group(Vec::<IntKey>::new()), Ok(()));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+struct SmallKey(u16);
+
+impl UnifyKey for SmallKey {
+    type Value = ();
+    type Index = u16;
+    fn index(&self) -> u16 {
+        self.0
+    }
+    fn from_index(u: u16) -> SmallKey {
+        SmallKey(u)
+    }
+    fn tag() -> &'static str {
+        "SmallKey"
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+struct WideKey(u64);
+
+impl UnifyKey for WideKey {
+    type Value = ();
+    type Index = u64;
+    fn index(&self) -> u64 {
+        self.0
+    }
+    fn from_index(u: u64) -> WideKey {
+        WideKey(u)
+    }
+    fn tag() -> &'static str {
+        "WideKey"
+    }
+}
+
+#[test]
+fn wide_key() {
+    let mut ut: InPlaceUnificationTable<WideKey> = UnificationTable::new();
+    let keys = ut.new_keys(10, |_| ());
+    assert_eq!(keys, WideKey(0)..WideKey(10));
+    ut.union(WideKey(3), WideKey(7));
+    assert!(ut.unioned(WideKey(7), WideKey(3)));
+    assert_eq!(ut.try_new_key(()), Ok(WideKey(10)));
+}
+
+#[test]
+fn try_new_key_overflow() {
+    all_modes! {
+        S for SmallKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            ut.new_keys(u16::MAX as usize, |_| ());
+            assert_eq!(ut.try_new_key(()), Ok(SmallKey(u16::MAX)));
+            assert_eq!(ut.try_new_key(()), Err(KeyOverflowError));
+            assert_eq!(ut.len(), 1 << 16);
+
+            // Retired slots can still be reused.
+            ut.retire_key(SmallKey(5));
+            assert_eq!(ut.try_new_key(()), Ok(SmallKey(5)));
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "SmallKey: too many keys for the index type of the key")]
+fn new_key_overflow() {
+    let mut ut: InPlaceUnificationTable<SmallKey> = UnificationTable::new();
+    ut.new_keys(u16::MAX as usize, |_| ());
+    ut.new_key(());
+    ut.new_key(());
+}
+
+#[test]
+#[should_panic(expected = "SmallKey: key index 65537 overflows the index type of the key")]
+fn new_keys_overflow() {
+    let mut ut: InPlaceUnificationTable<SmallKey> = UnificationTable::new();
+    ut.new_keys(1 << 16 | 1, |_| ());
+}
+
+#[test]
+fn frozen_too_many_keys() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    ut.new_keys(1 << 16 | 1, |_| ());
+    let mut bytes = vec![];
+    ut.write_frozen::<UnitCodec, _>(&mut bytes).unwrap();
+
+    assert!(FrozenUnificationTable::<UnitKey, UnitCodec>::new(&bytes).is_ok());
+    assert_eq!(
+        FrozenUnificationTable::<SmallKey, UnitCodec>::new(&bytes).err(),
+        Some(FrozenError::TooManyKeys(1 << 16 | 1))
+    );
+}
+
+#[test]
+fn packed_layout() {
+    let mut ut: UnificationTable<Packed<IntKey>, (BySize, FullCompression)> =
+        UnificationTable::new();
+    ut.new_keys(4, |_| None);
+    ut.unify_var_var(IntKey(0), IntKey(1)).unwrap();
+    ut.unify_var_var(IntKey(2), IntKey(1)).unwrap();
+    ut.unify_var_value(IntKey(2), Some(7)).unwrap();
+    let root = ut.find(IntKey(0));
+    assert_eq!(ut.rank(root), 3);
+    assert_eq!(ut.probe_value(IntKey(0)), Some(7));
+
+    let snapshot = ut.snapshot();
+    ut.unify_var_var(IntKey(3), IntKey(0)).unwrap();
+    let root3 = ut.find(IntKey(3));
+    assert_eq!(ut.rank(root3), 4);
+    assert_eq!(ut.probe_value(IntKey(3)), Some(7));
+    ut.rollback_to(snapshot);
+
+    assert_eq!(ut.find(IntKey(0)), root);
+    assert_eq!(ut.rank(root), 3);
+    assert_eq!(ut.parent(IntKey(3)), None);
+    assert_eq!(ut.probe_value(IntKey(3)), None);
+}
+
+thread_local! {
+    static VALUE_CLONES: Cell<usize> = const { Cell::new(0) };
+}
+
+// A value that counts how many times it is cloned.
+#[derive(Debug, PartialEq)]
+struct CountedValue(u32);
+
+impl Clone for CountedValue {
+    fn clone(&self) -> CountedValue {
+        VALUE_CLONES.with(|clones| clones.set(clones.get() + 1));
+        CountedValue(self.0)
+    }
+}
+
+impl UnifyValue for CountedValue {
+    type Error = NoError;
+
+    fn unify_values(a: &CountedValue, b: &CountedValue) -> Result<CountedValue, NoError> {
+        Ok(CountedValue(cmp::max(a.0, b.0)))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct CountedKey(u32);
+
+impl UnifyKey for CountedKey {
+    type Value = CountedValue;
+    type Index = u32;
+    fn index(&self) -> u32 {
+        self.0
+    }
+    fn from_index(u: u32) -> CountedKey {
+        CountedKey(u)
+    }
+    fn tag() -> &'static str {
+        "CountedKey"
+    }
+}
+
+#[test]
+fn no_value_clones_in_snapshot() {
+    #[cfg(test)]
+    fn test_body<S: Default + UnificationStore<Key = CountedKey, Value = CountedValue>>() {
+        let mut ut: UnificationTable<S> = UnificationTable::new();
+        let keys = ut.new_keys(64, |key| CountedValue(key.0));
+
+        VALUE_CLONES.with(|clones| clones.set(0));
+        let snapshot = ut.snapshot();
+        for i in 1..64 {
+            ut.union(CountedKey(i - 1), CountedKey(i));
+        }
+        for i in 0..64 {
+            ut.find(CountedKey(i));
+        }
+        ut.union_value(keys.start, CountedValue(100));
+        assert_eq!(VALUE_CLONES.with(|clones| clones.get()), 0);
+
+        ut.rollback_to(snapshot);
+        for i in 0..64 {
+            assert_eq!(ut.find(CountedKey(i)), CountedKey(i));
+            assert_eq!(ut.probe_value(CountedKey(i)), CountedValue(i));
+        }
+    }
+
+    test_body::<InPlace<CountedKey>>();
+    test_body::<Packed<CountedKey>>();
+}
+
+#[test]
+fn cloned_table_snapshot() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            let mut clone = ut.clone();
+
+            // Both tables have one snapshot open, so only their ids tell
+            // the snapshots apart.
+            let snapshot = ut.snapshot();
+            let cloned_snapshot = clone.snapshot();
+            clone.union(k1, k2);
+            let (cloned_snapshot, err) = ut.try_rollback_to(cloned_snapshot).unwrap_err();
+            assert_eq!(err, SnapshotError::Foreign);
+            assert!(!ut.unioned(k1, k2));
+            let (snapshot, err) = clone.try_commit(snapshot).unwrap_err();
+            assert_eq!(err, SnapshotError::Foreign);
+
+            assert!(clone.try_rollback_to(cloned_snapshot).is_ok());
+            assert!(!clone.unioned(k1, k2));
+            assert!(ut.try_commit(snapshot).is_ok());
+        }
+    }
+}
+
+#[test]
+fn by_size_saturates() {
+    assert_eq!(BySize::merge_ranks(3, 4), 7);
+    assert_eq!(BySize::merge_ranks(u32::MAX - 3, 2), u32::MAX - 1);
+    assert_eq!(
+        BySize::merge_ranks(u32::MAX - 1, u32::MAX - 1),
+        u32::MAX - 1
+    );
+}
+
+#[test]
+fn retire_key_by_size() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S, (BySize, NoCompression)> = UnificationTable::new();
+            let keys: Vec<_> = (0..3).map(|_| ut.new_key(())).collect();
+            ut.union(keys[0], keys[1]);
+            ut.union(keys[1], keys[2]);
+            let root = ut.find(keys[0]);
+            assert_eq!(ut.rank(root), 3);
+
+            // Retiring a member shrinks the size of the root...
+            let member = keys.iter().cloned().find(|&k| k != root).unwrap();
+            ut.retire_key(member);
+            assert_eq!(ut.find(root), root);
+            assert_eq!(ut.rank(root), 2);
+
+            // ...and retiring the root hands the smaller size on.
+            let last = keys.iter().cloned().find(|&k| k != root && k != member).unwrap();
+            ut.retire_key(root);
+            assert_eq!(ut.find(last), last);
+            assert_eq!(ut.rank(last), 1);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Weighted (or "potential") union-find.
+//!
+//! In a `WeightedUnificationTable`, keys are related by *offsets*
+//! rather than plain equality: unifying `a` and `b` with offset `c`
+//! records that `a = b + c`. Offsets are elements of a `Group`, so the
+//! relation is closed under composition and inversion: from `a = b + 1`
+//! and `b = c + 2` we learn `a = c + 3` and `c = a - 3`.
+//!
+//! Every parent edge carries the offset of the child relative to its
+//! parent, and `find` returns the root of a key along with the total
+//! offset of the key relative to that root. Path compression folds
+//! the offsets of the skipped edges into the new edge, so the usual
+//! near-constant time bounds still hold.
+//!
+//! Unifying two keys that are already in the same class with an offset
+//! that disagrees with the known one is an error. As with
+//! `EqUnifyValue`, the error is the pair of the two disagreeing
+//! values, `(known, requested)`.
+
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+use snapshot_vec as sv;
+
+use super::{key_from_usize, UnifyIndex, UnifyKey};
+
+/// A group, in the algebraic sense, used for the offsets in a
+/// `WeightedUnificationTable`. Implementations must satisfy the group
+/// laws: `combine` is associative, `identity` is neutral for it, and
+/// combining an element with its `inverse` yields the identity.
+///
+/// The group need not be commutative. An offset `c` relates `a` and
+/// `b` as `a = b.combine(c)`.
+///
+/// This crate implements `Group` for the signed integer types, under
+/// wrapping addition: offsets are integers modulo `2^n`, so large
+/// offsets wrap around instead of overflowing.
+pub trait Group: Clone + Debug + PartialEq {
+    /// The neutral element: `a = b + identity()` means `a = b`.
+    fn identity() -> Self;
+
+    /// Composes two offsets: first `self`, then `other`.
+    fn combine(&self, other: &Self) -> Self;
+
+    /// The offset that undoes `self`.
+    fn inverse(&self) -> Self;
+}
+
+macro_rules! impl_additive_group {
+    ($($t:ty),*) => {
+        $(
+            impl Group for $t {
+                fn identity() -> $t {
+                    0
+                }
+
+                fn combine(&self, other: &$t) -> $t {
+                    self.wrapping_add(*other)
+                }
+
+                fn inverse(&self) -> $t {
+                    self.wrapping_neg()
+                }
+            }
+        )*
+    };
+}
+
+impl_additive_group!(i8, i16, i32, i64, i128, isize);
+
+/// Value of a key in a `WeightedUnificationTable`: like `VarValue`,
+/// but the parent edge carries the offset of this key relative to its
+/// parent (`self = parent + weight`).
+#[derive(PartialEq, Clone, Debug)]
+pub struct WeightedVarValue<K, G> {
+    parent: K, // if equal to self, this is a root
+    weight: G, // offset relative to `parent`
+    rank: u32, // max depth (only relevant to root)
+}
+
+#[doc(hidden)]
+#[derive(Copy, Clone, Debug)]
+pub struct WeightedDelegate<K, G>(PhantomData<(K, G)>);
+
+impl<K, G> sv::SnapshotVecDelegate for WeightedDelegate<K, G> {
+    type Value = WeightedVarValue<K, G>;
+    type Undo = ();
+
+    fn reverse(_: &mut Vec<WeightedVarValue<K, G>>, _: ()) {}
+}
+
+/// Table of keys related by offsets drawn from the group `G`. See the
+/// module documentation for details. The `Value` type of `K` is not
+/// used; `()` is the natural choice.
+#[derive(Clone, Debug)]
+pub struct WeightedUnificationTable<K: UnifyKey, G: Group> {
+    values: sv::SnapshotVec<WeightedDelegate<K, G>>,
+}
+
+/// At any time, users may snapshot a weighted unification table. The
+/// changes made during the snapshot may either be *committed* or
+/// *rolled back*.
+pub struct WeightedSnapshot<K, G> {
+    snapshot: sv::Snapshot,
+    marker: PhantomData<(K, G)>,
+}
+
+// Manual impl avoids `Default` bounds on `K` and `G`.
+impl<K: UnifyKey, G: Group> Default for WeightedUnificationTable<K, G> {
+    fn default() -> Self {
+        WeightedUnificationTable {
+            values: sv::SnapshotVec::new(),
+        }
+    }
+}
+
+impl<K: UnifyKey, G: Group> WeightedUnificationTable<K, G> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of keys created so far.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Creates a fresh key, unrelated to any other key.
+    pub fn new_key(&mut self) -> K {
+        let len = self.values.len();
+        let key = key_from_usize(len);
+        self.values.push(WeightedVarValue {
+            parent: key,
+            weight: G::identity(),
+            rank: 0,
+        });
+        debug!("{}: created new key: {:?}", K::tag(), key);
+        key
+    }
+
+    /// Starts a new snapshot. Each snapshot must be either
+    /// rolled back or committed in a "LIFO" (stack) order.
+    pub fn snapshot(&mut self) -> WeightedSnapshot<K, G> {
+        WeightedSnapshot {
+            snapshot: self.values.start_snapshot(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Reverses all changes since the last snapshot. Also
+    /// removes any keys that have been created since then.
+    pub fn rollback_to(&mut self, snapshot: WeightedSnapshot<K, G>) {
+        debug!("{}: rollback_to()", K::tag());
+        self.values.rollback_to(snapshot.snapshot);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: WeightedSnapshot<K, G>) {
+        debug!("{}: commit()", K::tag());
+        self.values.commit(snapshot.snapshot);
+    }
+
+    fn value(&self, key: K) -> &WeightedVarValue<K, G> {
+        &self.values[key.index().as_usize()]
+    }
+
+    /// Given a key, returns its (current) root key and its offset
+    /// relative to that root, so that `key = root + offset`. This
+    /// performs path compression.
+    pub fn find<K1: Into<K>>(&mut self, id: K1) -> (K, G) {
+        self.get_root_key(id.into())
+    }
+
+    /// read-only version of `find`, no path-compression
+    pub fn read_find<K1: Into<K>>(&self, id: K1) -> (K, G) {
+        let mut key = id.into();
+        let mut offsets = vec![];
+        loop {
+            let value = self.value(key);
+            if value.parent == key {
+                break;
+            }
+            offsets.push(value.weight.clone());
+            key = value.parent;
+        }
+        // `offsets` runs from the key up to the root; compose it from
+        // the root down.
+        let offset = offsets
+            .iter()
+            .rev()
+            .fold(G::identity(), |acc, weight| acc.combine(weight));
+        (key, offset)
+    }
+
+    fn get_root_key(&mut self, vid: K) -> (K, G) {
+        let (redirect, weight) = {
+            let value = self.value(vid);
+            if value.parent == vid {
+                return (vid, G::identity());
+            }
+            (value.parent, value.weight.clone())
+        };
+
+        let (root_key, redirect_offset) = self.get_root_key(redirect);
+        let offset = redirect_offset.combine(&weight);
+        if root_key != redirect {
+            // Path compression
+            let new_weight = offset.clone();
+            self.values.update(vid.index().as_usize(), |value| {
+                value.parent = root_key;
+                value.weight = new_weight;
+            });
+        }
+
+        (root_key, offset)
+    }
+
+    /// Given two keys, indicates whether they are related.
+    pub fn unioned<K1: Into<K>, K2: Into<K>>(&mut self, a_id: K1, b_id: K2) -> bool {
+        self.find(a_id).0 == self.find(b_id).0
+    }
+
+    /// Returns the offset `c` such that `a = b + c`, or `None` if the
+    /// two keys are not related.
+    pub fn offset<K1: Into<K>, K2: Into<K>>(&mut self, a_id: K1, b_id: K2) -> Option<G> {
+        let (root_a, offset_a) = self.find(a_id);
+        let (root_b, offset_b) = self.find(b_id);
+        if root_a == root_b {
+            Some(offset_b.inverse().combine(&offset_a))
+        } else {
+            None
+        }
+    }
+
+    /// Records that `a = b + c`. If the two keys are already related
+    /// by a different offset, returns `Err((known, c))`, where `known`
+    /// is the offset that was already implied, and makes no change.
+    pub fn unify_var_var<K1, K2>(&mut self, a_id: K1, b_id: K2, c: G) -> Result<(), (G, G)>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        let (root_a, offset_a) = self.find(a_id);
+        let (root_b, offset_b) = self.find(b_id);
+
+        if root_a == root_b {
+            let known = offset_b.inverse().combine(&offset_a);
+            return if known == c { Ok(()) } else { Err((known, c)) };
+        }
+
+        // We have `a = root_a + offset_a` and `a = b + c = root_b +
+        // offset_b + c`, so `root_a = root_b + (offset_b + c - offset_a)`.
+        let weight = offset_b.combine(&c).combine(&offset_a.inverse());
+
+        let rank_a = self.value(root_a).rank;
+        let rank_b = self.value(root_b).rank;
+        if rank_a > rank_b {
+            self.redirect_root(rank_a, root_b, root_a, weight.inverse());
+        } else if rank_a < rank_b {
+            self.redirect_root(rank_b, root_a, root_b, weight);
+        } else {
+            self.redirect_root(rank_b + 1, root_a, root_b, weight);
+        }
+        Ok(())
+    }
+
+    /// Internal method to redirect `old_root_key` (which is currently
+    /// a root) to a child of `new_root_key` (which will remain a
+    /// root), such that `old_root_key = new_root_key + weight`.
+    fn redirect_root(&mut self, new_rank: u32, old_root_key: K, new_root_key: K, weight: G) {
+        debug!(
+            "{}: redirect_root({:?} -> {:?}, {:?})",
+            K::tag(),
+            old_root_key,
+            new_root_key,
+            weight
+        );
+        self.values
+            .update(old_root_key.index().as_usize(), |value| {
+                value.parent = new_root_key;
+                value.weight = weight;
+            });
+        self.values
+            .update(new_root_key.index().as_usize(), |value| {
+                value.rank = new_rank
+            });
+    }
+}
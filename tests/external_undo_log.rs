@@ -3,6 +3,7 @@ extern crate log;
 extern crate ena;
 
 use ena::{
+    bitvec::{BitWords, SnapshotBitVectorStorage},
     snapshot_vec as sv,
     undo_log::{Rollback, Snapshots, UndoLogs},
     unify::{self as ut, EqUnifyValue, UnifyKey},
@@ -13,6 +14,7 @@ struct IntKey(u32);
 
 impl UnifyKey for IntKey {
     type Value = Option<IntKey>;
+    type Index = u32;
     fn index(&self) -> u32 {
         self.0
     }
@@ -29,6 +31,7 @@ impl EqUnifyValue for IntKey {}
 enum UndoLog {
     EqRelation(sv::UndoLog<ut::Delegate<IntKey>>),
     Values(sv::UndoLog<i32>),
+    Bits(sv::UndoLog<BitWords>),
 }
 
 impl From<sv::UndoLog<ut::Delegate<IntKey>>> for UndoLog {
@@ -43,11 +46,18 @@ impl From<sv::UndoLog<i32>> for UndoLog {
     }
 }
 
+impl From<sv::UndoLog<BitWords>> for UndoLog {
+    fn from(l: sv::UndoLog<BitWords>) -> Self {
+        UndoLog::Bits(l)
+    }
+}
+
 impl Rollback<UndoLog> for TypeVariableStorage {
     fn reverse(&mut self, undo: UndoLog) {
         match undo {
             UndoLog::EqRelation(undo) => self.eq_relations.reverse(undo),
             UndoLog::Values(undo) => self.values.reverse(undo),
+            UndoLog::Bits(undo) => self.diverging.reverse(undo),
         }
     }
 }
@@ -57,6 +67,8 @@ struct TypeVariableStorage {
     values: sv::SnapshotVecStorage<i32>,
 
     eq_relations: ut::UnificationTableStorage<IntKey>,
+
+    diverging: SnapshotBitVectorStorage,
 }
 
 impl TypeVariableStorage {
@@ -81,7 +93,10 @@ struct TypeVariableTable<'a> {
 
 impl TypeVariableTable<'_> {
     fn new(&mut self, i: i32) -> IntKey {
-        self.storage.values.with_log(&mut self.undo_log).push(i);
+        let index = self.storage.values.with_log(&mut self.undo_log).push(i);
+        let mut diverging = self.storage.diverging.with_log(&mut self.undo_log);
+        diverging.grow(index + 1);
+        diverging.insert(index);
         self.storage
             .eq_relations
             .with_log(&mut self.undo_log)
@@ -196,7 +211,60 @@ fn external_undo_log() {
     storage.with_log(&mut undo_log).new(1);
     storage.with_log(&mut undo_log).new(2);
     assert_eq!(storage.len(), 2);
+    assert!(storage.diverging.contains(1));
 
     undo_log.rollback_to(|| &mut storage, snapshot);
     assert_eq!(storage.len(), 0);
+    assert!(storage.diverging.is_empty());
+}
+
+/// Tests the snapshot combinators provided by `Snapshots`
+#[test]
+fn external_undo_log_combinators() {
+    let mut storage = TypeVariableStorage::default();
+    let mut undo_log = TypeVariableUndoLogs::default();
+
+    let len = undo_log.probe(&mut storage, |undo_log, storage| {
+        storage.with_log(undo_log).new(1);
+        storage.len()
+    });
+    assert_eq!(len, 1);
+    assert_eq!(storage.len(), 0);
+
+    let result: Result<(), ()> = undo_log.commit_if_ok(&mut storage, |undo_log, storage| {
+        storage.with_log(undo_log).new(1);
+        Err(())
+    });
+    assert!(result.is_err());
+    assert_eq!(storage.len(), 0);
+
+    let key = undo_log.commit_unconditionally(&mut storage, |undo_log, storage| {
+        storage.with_log(undo_log).new(1)
+    });
+    assert_eq!(key, IntKey(0));
+    assert_eq!(storage.len(), 1);
+    assert_eq!(undo_log.num_open_snapshots, 0);
+}
+
+/// Tests that a guard obtained from `Snapshots` rolls back when dropped
+#[test]
+fn external_undo_log_guard() {
+    let mut storage = TypeVariableStorage::default();
+    let mut undo_log = TypeVariableUndoLogs::default();
+
+    {
+        let mut guard = undo_log.snapshot_guard(&mut storage);
+        let (undo_log, storage) = guard.parts();
+        storage.with_log(undo_log).new(1);
+        assert_eq!(storage.len(), 1);
+    }
+    assert_eq!(storage.len(), 0);
+    assert_eq!(undo_log.num_open_snapshots, 0);
+
+    let mut guard = undo_log.snapshot_guard(&mut storage);
+    let (log, storage_ref) = guard.parts();
+    storage_ref.with_log(log).new(1);
+    guard.commit();
+    assert_eq!(storage.len(), 1);
+    assert_eq!(undo_log.num_open_snapshots, 0);
 }